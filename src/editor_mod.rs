@@ -0,0 +1,94 @@
+use super::*;
+
+/// Lets you put pieces wherever you want, and only complains when you are done.\
+/// Get one with `Game::editor()`.
+#[derive(Clone)]
+pub struct BoardEditor {
+    board: [Option<Piece>; SQUARES],
+    turn_owner: Color,
+    /// The first thing that went wrong, saved for `build`.
+    error: Option<String>,
+}
+
+impl Game {
+    /// An empty board with White to move, ready to be filled with pieces.
+    pub fn editor() -> BoardEditor {
+        BoardEditor {
+            board: std::array::from_fn(|_| None),
+            turn_owner: Color::White,
+            error: None,
+        }
+    }
+
+    /// Makes a game from a list of (col, row, rank, color), which is a lot nicer than a template
+    /// when there are only a few pieces. Fails just like `BoardEditor::build`.
+    pub fn from_placements(
+        placements: &[(u8, u8, char, Color)],
+        turn: Color,
+    ) -> Result<Game, String> {
+        placements
+            .iter()
+            .fold(Game::editor(), |e, &(col, row, rank, color)| {
+                e.place(col, row, rank, color)
+            })
+            .set_turn(turn)
+            .build()
+    }
+}
+
+impl BoardEditor {
+    /// Puts a piece on the square, replacing whatever was there.
+    /// The ranks are the same as for `make_board`.
+    pub fn place(mut self, col: u8, row: u8, rank: char, color: Color) -> BoardEditor {
+        if col >= BOARD_SIZE || row >= BOARD_SIZE {
+            self.fail(format!("({},{}) is outside the board", col, row));
+        } else if !"KQRBNp".contains(rank) {
+            self.fail(format!("There is no rank called {}", rank));
+        } else {
+            self.board[pack_square(col, row) as usize] = Some(Piece::new(color, rank));
+        }
+        self
+    }
+
+    /// Takes away whatever is on the square.
+    pub fn remove(mut self, col: u8, row: u8) -> BoardEditor {
+        if col >= BOARD_SIZE || row >= BOARD_SIZE {
+            self.fail(format!("({},{}) is outside the board", col, row));
+        } else {
+            self.board[pack_square(col, row) as usize] = None;
+        }
+        self
+    }
+
+    /// Decides who gets to move first.
+    pub fn set_turn(mut self, color: Color) -> BoardEditor {
+        self.turn_owner = color;
+        self
+    }
+
+    /// Makes the game, unless something was placed wrong or one of the players has no crucial piece.
+    pub fn build(self) -> Result<Game, String> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        for color in [Color::White, Color::Black] {
+            if !self
+                .board
+                .iter()
+                .flatten()
+                .any(|p| p.is_crucial && p.color == color)
+            {
+                return Err(format!("{:?} needs a crucial piece", color));
+            }
+        }
+
+        Ok(Game::from_parts(self.board, self.turn_owner, 1))
+    }
+
+    fn fail(&mut self, error: String) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+}
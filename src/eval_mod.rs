@@ -0,0 +1,66 @@
+use super::*;
+
+/// Which line the pawn walks along (the column for normal pawns),
+/// and how far along it the pawn has come.
+fn pawn_line(p: &Piece, pos: (u8, u8)) -> (i8, i8) {
+    let (col, row) = (pos.0 as i8, pos.1 as i8);
+    if p.forward.0 == 0 {
+        (col, row * p.forward.1)
+    } else {
+        (row, col * p.forward.0)
+    }
+}
+
+impl Game {
+    /// Pawns of `color` that share their line with another pawn of the same color. All of them are listed.
+    pub fn doubled_pawns(&self, color: Color) -> Vec<(u8, u8)> {
+        let pawns = self.pawn_lines(color);
+        pawns
+            .iter()
+            .filter(|(_, (line, _))| pawns.iter().filter(|(_, (l, _))| l == line).count() > 1)
+            .map(|(pos, _)| *pos)
+            .collect()
+    }
+
+    /// Pawns of `color` with no pawn of the same color on either line next to them.
+    pub fn isolated_pawns(&self, color: Color) -> Vec<(u8, u8)> {
+        let pawns = self.pawn_lines(color);
+        pawns
+            .iter()
+            .filter(|(_, (line, _))| !pawns.iter().any(|(_, (l, _))| (l - line).abs() == 1))
+            .map(|(pos, _)| *pos)
+            .collect()
+    }
+
+    /// Pawns of `color` that no enemy pawn can stop, because there are none in front of it,
+    /// on its own line or the ones next to it.\
+    /// "In front" follows the direction the pawn walks, so this works for sideways pawns too.
+    pub fn passed_pawns(&self, color: Color) -> Vec<(u8, u8)> {
+        let enemies: Vec<(u8, u8)> = self.find_pieces(color.opposite(), 'p');
+
+        self.find_pieces(color, 'p')
+            .into_iter()
+            .filter(|&pos| {
+                let p = self.get_piece_at(pos.0, pos.1).unwrap();
+                let (line, progress) = pawn_line(p, pos);
+                !enemies.iter().any(|&e| {
+                    // Measured the same way as our pawn, so "ahead" means the same thing.
+                    let (l, prog) = pawn_line(p, e);
+                    (l - line).abs() <= 1 && prog > progress
+                })
+            })
+            .collect()
+    }
+
+    fn pawn_lines(&self, color: Color) -> Vec<((u8, u8), (i8, i8))> {
+        self.find_pieces(color, 'p')
+            .into_iter()
+            .map(|pos| {
+                (
+                    pos,
+                    pawn_line(self.get_piece_at(pos.0, pos.1).unwrap(), pos),
+                )
+            })
+            .collect()
+    }
+}
@@ -1,12 +1,30 @@
 use core::fmt::Display;
 
 /// Used to track piece alignment and who's turn it is.
-#[derive(PartialEq, Clone, Copy, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum Color {
     White,
     Black,
 }
 
+impl Color {
+    /// 0 for White, 1 for Black. For when you have one of something per player.
+    pub fn index(&self) -> usize {
+        match self {
+            Color::White => 0,
+            Color::Black => 1,
+        }
+    }
+
+    /// The other one.
+    pub fn opposite(&self) -> Color {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+}
+
 /// Moves such as Castling and en passant affect pieces on squares other than the one they land on.\
 /// These are used to describe such effects.
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -30,6 +48,7 @@ impl Position {
     /// - A global position + a global position returns None (it breaks)\
     /// - A global position + a relative position returns a global position\
     /// - A relative position + a relative position returns a relative position
+    #[allow(clippy::should_implement_trait)]
     pub fn add(self, p2: Position) -> Option<Position> {
         match self {
             Position::Global((x, y)) => match p2 {
@@ -59,8 +78,27 @@ impl Display for Color {
     }
 }
 
+impl Display for Position {
+    /// Global positions are written like "e4", relative ones like "(+1,-2)".
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Position::Global((col, row)) => write!(f, "{}{}", (b'a' + col) as char, row + 1),
+            Position::Relative((col, row)) => write!(f, "({:+},{:+})", col, row),
+        }
+    }
+}
+
+impl Display for Effect {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Effect::Capture(p) => write!(f, "x {}", p),
+            Effect::Move(p1, p2) => write!(f, "{}→{}", p1, p2),
+        }
+    }
+}
+
 /// Describes, you guessed it, the state of the game.
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum GameState {
     /// Everything is running fine.
     Running,
@@ -72,28 +110,263 @@ pub enum GameState {
     CheckMate,
     /// It is a stalemate, and the game is a draw.
     Stalemate,
-    /// Hopefully I will never have to use this one.
-    /// But I would rather have it and not need it, than need it and not have it.
-    SomethingHasGoneTerriblyWrongMilord,
+    /// The turn owner has no crucial pieces left, so they lost.
+    /// Only happens when `RuleSet::capture_the_king` is on.
+    CrucialCaptured,
+    /// The turn owner has no moves, and that means they won.
+    /// Only happens when `RuleSet::stalemate_wins` is on.
+    StalemateWin,
+}
+
+/// A single move, with the rank to promote to if the move needs it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChessMove {
+    pub from: (u8, u8),
+    pub to: (u8, u8),
+    /// Must be Some if (and only if) the move ends in a promotion.
+    pub promotion: Option<char>,
+}
+
+/// Why a move could not be made.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MoveError {
+    /// There is no piece to move.
+    NoPiece,
+    /// The piece belongs to the other player.
+    WrongColor,
+    /// The piece can not make that move.
+    IllegalMove,
+    /// The game is over, or it is waiting for a promotion.
+    WrongState,
+    /// The move ends in a promotion, but no rank was given.
+    MissingPromotion,
+    /// The rank can not be promoted to, or the move does not promote at all.
+    InvalidPromotion,
+    /// The SAN string does not match exactly one legal move.
+    UnknownSan,
+    /// One of the move's effects points off the board, or moves a piece that is not there.
+    /// This only happens with broken custom moves, so it is a bug in the move, not in your input.
+    BrokenMove,
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MoveError::NoPiece => write!(f, "There is no piece to move"),
+            MoveError::WrongColor => write!(f, "That piece belongs to the other player"),
+            MoveError::IllegalMove => write!(f, "The piece can not make that move"),
+            MoveError::WrongState => write!(f, "No moves can be made right now"),
+            MoveError::MissingPromotion => write!(f, "The move needs a rank to promote to"),
+            MoveError::InvalidPromotion => write!(f, "That is not a valid promotion"),
+            MoveError::UnknownSan => {
+                write!(f, "That is not a legal move, or it could be more than one")
+            }
+            MoveError::BrokenMove => write!(
+                f,
+                "Something has gone terribly wrong, milord: the move has an effect that can not happen"
+            ),
+        }
+    }
+}
+
+/// Something that happened while making a move.
+/// Replaying them in order gets you from the old board to the new one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameEvent {
+    PieceMoved {
+        from: (u8, u8),
+        to: (u8, u8),
+    },
+    PieceCaptured {
+        at: (u8, u8),
+        rank: char,
+    },
+    PiecePromoted {
+        at: (u8, u8),
+        from_rank: char,
+        to_rank: char,
+    },
+    TurnChanged {
+        to: Color,
+    },
+    StateChanged {
+        to: GameState,
+    },
+}
+
+/// Everything a UI wants to know about the game between moves, in one go. Get it with `Game::status`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GameStatus {
+    pub turn_owner: Color,
+    pub game_state: GameState,
+    /// Starts at 1, and goes up after Black moves.
+    pub turn_count: u32,
+    /// Half moves since the last capture or pawn move.
+    pub halfmove_clock: u32,
+    pub in_check: bool,
+    pub can_claim_draw: bool,
+    /// None if nothing has been moved yet (or the last thing was a crazyhouse drop).
+    pub last_move: Option<ChessMove>,
+}
+
+/// Which of the weird rules are in play. Everything is on by default.\
+/// Turn them off if you are teaching someone and do not want to explain en passant yet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RuleSet {
+    pub castling: bool,
+    pub en_passant: bool,
+    /// Pawns moving two steps on their first move.
+    pub double_pawn_step: bool,
+    /// Check and check mate are forgotten about. You are allowed to leave your crucial pieces
+    /// in danger, and you lose when the last one is captured. Off by default.
+    pub capture_the_king: bool,
+    /// Captured pieces go to the capturer's hand, and can be dropped back on the board
+    /// with `Game::drop` instead of making a move. Off by default.
+    pub crazyhouse: bool,
+    /// Having no moves (without being in check) wins the game instead of drawing it,
+    /// like in antichess. Off by default.
+    pub stalemate_wins: bool,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet {
+            castling: true,
+            en_passant: true,
+            double_pawn_step: true,
+            capture_the_king: false,
+            crazyhouse: false,
+            stalemate_wins: false,
+        }
+    }
+}
+
+/// How many squares wide and tall the board is.
+pub const BOARD_SIZE: u8 = 8;
+
+/// How many squares there are on the whole board, which is also how long the board arrays are.
+pub const SQUARES: usize = BOARD_SIZE as usize * BOARD_SIZE as usize;
+
+/// Turns the single u8 squares that `get_moves` and friends use into (col, row).
+pub fn unpack_square(sq: u8) -> (u8, u8) {
+    (sq % BOARD_SIZE, sq / BOARD_SIZE)
+}
+
+/// Turns (col, row) into a single u8 square, the opposite of `unpack_square`.
+pub fn pack_square(col: u8, row: u8) -> u8 {
+    col + row * BOARD_SIZE
+}
+
+fn gcd(a: u8, b: u8) -> u8 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
 }
 
+/// The ranks that a normal pawn can promote to.
+pub const PROMOTION_RANKS: [char; 4] = ['Q', 'R', 'B', 'N'];
+
+mod editor_mod;
+mod eval_mod;
+mod pgn_mod;
 mod piece_mod;
+mod render_mod;
+mod search_mod;
 use std::collections::*;
+use std::sync::OnceLock;
 
+pub use editor_mod::BoardEditor;
+pub use pgn_mod::{GameRecord, PgnReader};
 use piece_mod::*;
+pub use render_mod::RenderOptions;
+pub use search_mod::{Score, SearchResult, SearchStats, TranspositionTable};
+
+impl Default for Game {
+    /// The start position, but without any history or cached attacks, since those belong to a board.
+    /// That way `Game { board, ..Default::default() }` doesn't drag the start position along.\
+    /// Use `Game::new()` for a game you actually want to play.
+    fn default() -> Self {
+        let mut g = Game::new();
+        g.attacks = Default::default();
+        g.history.clear();
+        g
+    }
+}
+
+/// What counts as the same position when looking for repetitions:
+/// the board, who's turn it is, `castling_rights`, `en_passant_square` and what is in each hand.
+type PositionKey = ([char; SQUARES], Color, u64, Option<u8>, [Vec<char>; 2]);
 
 /// The thing with all the things in it!
 #[derive(Clone)]
 pub struct Game {
-    board: [Option<Piece>; 8 * 8],
+    board: [Option<Piece>; SQUARES],
     turn_owner: Color,
     turn_count: u32,
     game_state: GameState,
+    /// Every square attacked by White and by Black, in that order. Empty until someone asks.
+    attacks: [OnceLock<u64>; 2],
+    rules: RuleSet,
+    /// Every position the game has been in, including the current one at the end.
+    history: Vec<PositionKey>,
+    /// The moves made since the last one that can not be undone by moving back.
+    reversible: Vec<ChessMove>,
+    /// The ranks each player can drop, indexed by `Color::index`. Only used in crazyhouse.
+    hands: [Vec<char>; 2],
+    /// Half moves since the last capture or pawn move, for the fifty move rule.
+    halfmove_clock: u32,
+    last_move: Option<ChessMove>,
 }
 
 impl Game {
     /// Make a new, completely normal board.
     pub fn new() -> Game {
+        // White starts, and it is the 1st turn
+        Game::from_parts(Game::start_board(), Color::White, 1)
+    }
+
+    /// Puts everything back the way `Game::new()` makes it, rules included,
+    /// while keeping the memory that the history already has.
+    pub fn reset(&mut self) {
+        self.board = Game::start_board();
+        self.turn_owner = Color::White;
+        self.turn_count = 1;
+        self.attacks = Default::default();
+        self.rules = RuleSet::default();
+        self.history.clear();
+        self.reversible.clear();
+        for hand in &mut self.hands {
+            hand.clear();
+        }
+        self.halfmove_clock = 0;
+        self.last_move = None;
+        self.compute_state();
+        self.history.push(self.position_key());
+    }
+
+    /// A copy of the game that forgets how it got here, so it is cheap to make even late in a game.
+    /// The position, rules, hands and the fifty move count are kept.\
+    /// Repetitions and `reversible_history` start over from this position, so the copy can not
+    /// tell you about a draw by repetition that needs moves from before it. The search uses this.
+    pub fn clone_without_history(&self) -> Game {
+        Game {
+            board: self.board.clone(),
+            turn_owner: self.turn_owner,
+            turn_count: self.turn_count,
+            game_state: self.game_state,
+            attacks: self.attacks.clone(),
+            rules: self.rules,
+            history: vec![self.position_key()],
+            reversible: Vec::new(),
+            hands: self.hands.clone(),
+            halfmove_clock: self.halfmove_clock,
+            last_move: self.last_move,
+        }
+    }
+
+    fn start_board() -> [Option<Piece>; SQUARES] {
         let template = [
             'R', 'N', 'B', 'Q', 'K', 'B', 'N', 'R', // White side
             'p', 'p', 'p', 'p', 'p', 'p', 'p', 'p', //
@@ -107,21 +380,51 @@ impl Game {
 
         // Default             Black Empty White
         // piece map is      0xFFFF00000000FFFF
-        let white_map = 0x000000000000FFFF as u64;
+        let white_map = 0x000000000000FFFF_u64;
 
         // The default board should not crash
-        let board = Game::make_board(template, white_map).ok().unwrap();
-        Game {
-            board: board,
-            turn_owner: Color::White, // White starts
-            turn_count: 1,            // 1st turn
+        Game::make_board(template, white_map).ok().unwrap()
+    }
+
+    /// Makes a game from a board (see `make_board`), who's turn it is, and what turn it is.\
+    /// The game state is figured out from the board, so it will always be correct.\
+    /// Nothing before this position is known, so repetitions start counting from here.
+    pub fn from_parts(board: [Option<Piece>; SQUARES], turn_owner: Color, turn_count: u32) -> Game {
+        let mut g = Game {
+            board,
+            turn_owner,
+            turn_count,
             game_state: GameState::Running,
-        }
+            attacks: Default::default(),
+            rules: RuleSet::default(),
+            history: Vec::new(),
+            reversible: Vec::new(),
+            hands: [Vec::new(), Vec::new()],
+            halfmove_clock: 0,
+            last_move: None,
+        };
+        g.compute_state();
+        g.history.push(g.position_key());
+        g
     }
 
     /// In case you want to set up a custom board.
-    pub fn make_board(template: [char; 64], white_map: u64) -> Result<[Option<Piece>; 64], String> {
-        let mut board: [Option<Piece>; 64];
+    pub fn make_board(
+        template: [char; SQUARES],
+        white_map: u64,
+    ) -> Result<[Option<Piece>; SQUARES], String> {
+        Game::make_board_validated(template, white_map, false)
+    }
+
+    /// Same as `make_board`, but if `validate` is true it will also refuse boards
+    /// with pawns on the first or last row, since those can never happen in a legal game.\
+    /// Leave it off if you are making some weird variant.
+    pub fn make_board_validated(
+        template: [char; SQUARES],
+        white_map: u64,
+        validate: bool,
+    ) -> Result<[Option<Piece>; SQUARES], String> {
+        let mut board: [Option<Piece>; SQUARES];
         board = [
             None, None, None, None, None, None, None, None, // a
             None, None, None, None, None, None, None, None, // a
@@ -136,7 +439,7 @@ impl Game {
         let mut w_crucial = false;
         let mut b_crucial = false;
 
-        for i in 0..64 {
+        for i in 0..SQUARES {
             let rank = template[i];
 
             // Leave empty spots
@@ -152,6 +455,15 @@ impl Game {
             };
             let piece = Piece::new(color, rank);
 
+            // Pawns can not stand on the first or last row in a legal game.
+            let (col, row) = unpack_square(i as u8);
+            if validate && piece.rank == 'p' && (row == 0 || row == BOARD_SIZE - 1) {
+                return Err(format!(
+                    "There is a pawn on an impossible row at ({},{})",
+                    col, row
+                ));
+            }
+
             // Track if either side got a crucial piece (a "King")
             if piece.is_crucial {
                 match color {
@@ -172,278 +484,1072 @@ impl Game {
 
     /// Will move the piece, if it deems the move to be legal, of course.
     pub fn make_move(&mut self, from: (u8, u8), to: (u8, u8)) -> bool {
-        match self.game_state {
-            GameState::Running | GameState::Check => {}
-            _ => return false,
-        }
-
-        // println!("Moving from ({},{})", from.0, from.1);
-        if let Some(piece) = self.get_piece_at(from.0, from.1) {
-            // Do not move the opponent's piece
-            if self.turn_owner != piece.color {
-                // println!("{} can not move {}'s pieces", self.turn_owner, piece.color);
-                return false;
-            }
+        // Does it have the move?????
+        let effects = match self.check_move(from, to) {
+            Ok(effects) => effects,
+            Err(_) => return false,
+        };
 
-            let moves = piece.get_all_possible_moves(from.0, from.1, self);
+        self.record_move(from, to, &effects);
+        self.just_execute_move(from, to, &effects);
+        // IT DO!!!!!!!!!
 
-            // Does it have the move?????
-            if let Some(effects) = moves.get(&(to.0 + to.1 * 8)) {
-                self.just_execute_move(from, to, effects);
-                // IT DO!!!!!!!!!
+        // Do not move on until every single piece is promoted.
+        if self.game_state != GameState::Promote {
+            self.increment_turn();
+        }
 
-                // Do not move on until every single piece is promoted.
-                if self.game_state != GameState::Promote {
-                    self.increment_turn();
-                }
+        true
+    }
 
-                return true;
-            }
-            // print!("If you are seeing this, then things have gone terribly wrong.");
+    /// Tries out every move in order on a copy of the game, like `make` would.
+    /// If one fails, you get its index and why. The game itself is never changed.
+    pub fn validate_move_list(&self, moves: &[ChessMove]) -> Result<(), (usize, MoveError)> {
+        let mut g = self.clone();
+        for (i, &mv) in moves.iter().enumerate() {
+            g.make(mv).map_err(|e| (i, e))?;
         }
-        // println!("No piece found at ({},{})", from.0, from.1);
-        false
+        Ok(())
     }
 
-    /// This will perform the move without checking if ANYTHING is legal.
-    /// Caution is advised when calling directly
-    fn just_execute_move(&mut self, from: (u8, u8), to: (u8, u8), effects: &Vec<Effect>) {
-        self.just_move(from, to);
-        for e in effects {
-            match e {
-                Effect::Capture(p) => self.capture(position(*p, from)),
-                Effect::Move(p1, p2) => self.just_move(position(*p1, from), position(*p2, from)),
-            }
-        }
+    /// Like `make_move`, but if the move ends in a promotion, `choose` gets the ranks the piece can become
+    /// and picks one. `choose` is not called for moves that do not promote.\
+    /// Returns false (and changes nothing) if the move fails, or if `choose` picks a rank that is not on the list.
+    pub fn make_move_with(
+        &mut self,
+        from: (u8, u8),
+        to: (u8, u8),
+        choose: impl FnOnce(&[char]) -> char,
+    ) -> bool {
+        let promotion = match self.get_piece_at(from.0, from.1) {
+            Some(p) if self.is_promotion_move(from, to) => Some(choose(p.promotion_ranks())),
+            _ => None,
+        };
+        self.make(ChessMove {
+            from,
+            to,
+            promotion,
+        })
+        .is_ok()
     }
 
-    /// This will force pieces to move. Will crash if there is no piece to move because I can not be bothered to make it check first.
-    fn just_move(&mut self, from: (u8, u8), to: (u8, u8)) {
-        let piece = self.board[(from.0 + from.1 * 8) as usize]
-            .clone()
-            .expect("DO NOT USE just_move IF YOU DO NOT KNOW WHAT YOU ARE DOING!");
+    /// Like `make_move`, but it tells you why it failed, and it does the promotion too.\
+    /// Nothing is changed if the move fails.
+    pub fn make(&mut self, mv: ChessMove) -> Result<(), MoveError> {
+        self.check_move(mv.from, mv.to)?;
 
-        let piece2 = Piece {
-            last_moved: Some(self.turn_count),
-            times_moved: piece.times_moved + 1,
-            ..piece
-        };
+        let piece = self.get_piece_at(mv.from.0, mv.from.1).unwrap();
+        match (piece.promotes_at(mv.to.0, mv.to.1), mv.promotion) {
+            (true, None) => return Err(MoveError::MissingPromotion),
+            (true, Some(rank)) if !piece.promotion_ranks().contains(&rank) => {
+                return Err(MoveError::InvalidPromotion)
+            }
+            (false, Some(_)) => return Err(MoveError::InvalidPromotion),
+            _ => {}
+        }
 
-        if piece2.can_promote {
-            if to.1
-                == match piece2.color {
-                    Color::White => 7,
-                    Color::Black => 0,
+        match mv.promotion {
+            None => {
+                if !self.make_move(mv.from, mv.to) {
+                    return Err(MoveError::IllegalMove);
+                }
+            }
+            Some(rank) => {
+                // Some other piece might have to promote as well, and not be allowed to become `rank`.
+                let before = self.clone();
+                if !self.make_move(mv.from, mv.to) {
+                    return Err(MoveError::IllegalMove);
+                }
+                if !self.promote_all(rank) {
+                    *self = before;
+                    return Err(MoveError::InvalidPromotion);
                 }
-            {
-                // Promotion time
-                self.game_state = GameState::Promote;
             }
         }
-
-        self.board[(to.0 + to.1 * 8) as usize] = Some(piece2);
-        self.board[(from.0 + from.1 * 8) as usize] = None;
+        Ok(())
     }
 
-    /// Will remove the piece, no questions asked.
-    fn capture(&mut self, pos: (u8, u8)) {
-        self.board[(pos.0 + pos.1 * 8) as usize] = None;
+    /// Promotes every piece that is waiting for it to `rank`.\
+    /// Stops and returns false as soon as one of them is not allowed to become `rank`,
+    /// so whatever got promoted before that stays promoted.
+    fn promote_all(&mut self, rank: char) -> bool {
+        while let Some(pos) = self.promotion_square() {
+            if !self.promote(pos, rank) {
+                return false;
+            }
+        }
+        true
     }
 
-    /// (0,0) is bottom left. (7,7) is top right.\
-    /// Due to the structure of this thing, the default doc file does not
-    /// include any information about the Piece struct. However, you should
-    /// be able to find all the necessary stuff in the README file.
-    pub fn get_piece_at(&self, col: u8, row: u8) -> Option<&Piece> {
-        if col > 7 || row > 7 {
+    /// Makes the move on a copy of the game, with every promotion becoming a queen.\
+    /// None if the move can not be made, or if something can not become a queen.
+    fn after_move(&self, from: (u8, u8), to: (u8, u8)) -> Option<Game> {
+        let mut g = self.clone();
+        if !g.make_move(from, to) || !g.promote_all('Q') {
             return None;
         }
-        self.board[(row * 8 + col) as usize].as_ref()
+        Some(g)
     }
 
-    /// Why would you use this? Why did I make this public?
-    pub fn print_board(&self) {
-        // Row 0 is the bottom, but the console draws top to bottom.
-        for row in (0..8 as u8).rev() {
-            for col in 0..8 as u8 {
-                if (col + row) & 1 == 1 {
-                    print!("\x1b[7m");
-                }
+    /// Makes sure the move can be made, and gives you its effects if it can.
+    fn check_move(&self, from: (u8, u8), to: (u8, u8)) -> Result<Vec<Effect>, MoveError> {
+        match self.game_state {
+            GameState::Running | GameState::Check => {}
+            _ => return Err(MoveError::WrongState),
+        }
 
-                if let Some(p) = self.get_piece_at(col, row) {
-                    match p.color {
-                        Color::White => print!("({})", p.rank),
-                        Color::Black => print!("<{}>", p.rank),
-                    };
-                } else {
-                    print!("   ")
+        let piece = self
+            .get_piece_at(from.0, from.1)
+            .ok_or(MoveError::NoPiece)?;
+        if piece.color != self.turn_owner {
+            return Err(MoveError::WrongColor);
+        }
+
+        let effects = piece
+            .get_all_possible_moves(from.0, from.1, self)
+            .remove(&pack_square(to.0, to.1))
+            .ok_or(MoveError::IllegalMove)?;
+
+        // Better to refuse the move than to make half of it.
+        let on_board = |(c, r): (u8, u8)| c < BOARD_SIZE && r < BOARD_SIZE;
+        for e in &effects {
+            let fine = match e {
+                Effect::Capture(p) => on_board(position(*p, from)),
+                Effect::Move(p1, p2) => {
+                    let (c, r) = position(*p1, from);
+                    self.get_piece_at(c, r).is_some() && on_board(position(*p2, from))
                 }
-                print!("\x1b[0m");
+            };
+            if !fine {
+                return Err(MoveError::BrokenMove);
             }
-            println!();
         }
+        Ok(effects)
     }
 
-    /// Why would you use this? Why did I make this public?
-    pub fn print_moves(&self, col: u8, row: u8) {
-        if let Some(p) = self.get_piece_at(col, row) {
-            let moves = p.get_all_possible_moves(col, row, self);
-
-            for r in (0..8 as u8).rev() {
-                for c in 0..8 as u8 {
-                    if c == col && r == row {
-                        print!("\x1b[38;5;9m\x1b[48;5;1m");
-                    } else if moves.contains_key(&(c + r * 8)) {
-                        print!("\x1b[38;5;14m\x1b[48;5;14m");
-                    }
-                    if (c + r) & 1 == 1 {
-                        print!("\x1b[7m");
-                    }
-
-                    if let Some(p) = self.get_piece_at(c, r) {
-                        match p.color {
-                            Color::White => print!("({})", p.rank),
-                            Color::Black => print!("[{}]", p.rank),
-                        };
-                    } else {
-                        print!("   ")
+    /// Same as `make_move`, but it tells you everything that happened, in the order it happened.\
+    /// If the move ends in a promotion, the last event will be the state changing to `Promote`,
+    /// and you can get the rest of the events from `promote_events`.
+    pub fn make_move_events(
+        &mut self,
+        from: (u8, u8),
+        to: (u8, u8),
+    ) -> Result<Vec<GameEvent>, MoveError> {
+        let effects = self.check_move(from, to)?;
+        let mut events = Vec::new();
+
+        if let Some(p) = self.get_piece_at(to.0, to.1) {
+            events.push(GameEvent::PieceCaptured {
+                at: to,
+                rank: p.rank,
+            });
+        }
+        events.push(GameEvent::PieceMoved { from, to });
+        for e in &effects {
+            match e {
+                Effect::Capture(p) => {
+                    let at = position(*p, from);
+                    if let Some(p) = self.get_piece_at(at.0, at.1) {
+                        events.push(GameEvent::PieceCaptured { at, rank: p.rank });
                     }
-                    print!("\x1b[0m");
                 }
-                // println!("|");
-                println!();
+                Effect::Move(p1, p2) => events.push(GameEvent::PieceMoved {
+                    from: position(*p1, from),
+                    to: position(*p2, from),
+                }),
             }
         }
-    }
 
-    /// The color you give as an argument refers to who the space is safe FOR, not from.
-    pub fn is_safe_position(&self, col: u8, row: u8, color: Color) -> bool {
-        // Assume that every move that they can make in response is safe.
-        // This should (emphasis on should) stop any and all infinite loops.
-        if color != self.turn_owner {
-            return true;
+        let (owner, state) = (self.turn_owner, self.game_state);
+        self.record_move(from, to, &effects);
+        self.just_execute_move(from, to, &effects);
+        if self.game_state != GameState::Promote {
+            self.increment_turn();
         }
+        self.push_turn_events(owner, state, &mut events);
 
-        for i in 0..64 as u8 {
-            if let Some(piece) = self.get_piece_at(i % 8, i >> 3) {
-                if piece.color == color {
-                    continue;
-                }
-                let a = piece.get_danger_zone(i % 8, i >> 3, self);
-                if a.contains(&(col + row * 8)) {
-                    return false;
-                }
-            }
+        Ok(events)
+    }
+
+    /// Same as `promote`, but with events, like `make_move_events`.
+    pub fn promote_events(
+        &mut self,
+        pos: (u8, u8),
+        rank: char,
+    ) -> Result<Vec<GameEvent>, MoveError> {
+        if self.game_state != GameState::Promote {
+            return Err(MoveError::WrongState);
         }
-        true
+        let from_rank = self
+            .get_piece_at(pos.0, pos.1)
+            .ok_or(MoveError::NoPiece)?
+            .rank;
+
+        let (owner, state) = (self.turn_owner, self.game_state);
+        if !self.promote(pos, rank) {
+            return Err(MoveError::InvalidPromotion);
+        }
+
+        let mut events = vec![GameEvent::PiecePromoted {
+            at: pos,
+            from_rank,
+            to_rank: rank,
+        }];
+        self.push_turn_events(owner, state, &mut events);
+        Ok(events)
     }
 
-    /// The color you give as an argument refers to who the space is safe FOR, not from.
-    fn is_safe_move(
-        &self,
-        from: (u8, u8),
-        to: (u8, u8),
-        effects: &Vec<Effect>,
-        color: Color,
-    ) -> bool {
-        let mut gc = self.clone();
-        gc.just_execute_move(from, to, effects);
+    fn push_turn_events(&self, owner: Color, state: GameState, events: &mut Vec<GameEvent>) {
+        if self.turn_owner != owner {
+            events.push(GameEvent::TurnChanged {
+                to: self.turn_owner,
+            });
+        }
+        if self.game_state != state {
+            events.push(GameEvent::StateChanged {
+                to: self.game_state,
+            });
+        }
+    }
 
-        let mut i = 0;
-        for p in &gc.board {
-            if let Some(piece) = p {
-                if piece.is_crucial
-                    && piece.color == color
-                    && !gc.is_safe_position(i % 8, i >> 3, color)
-                {
-                    return false;
+    /// This will perform the move without checking if ANYTHING is legal.
+    /// Caution is advised when calling directly
+    fn just_execute_move(&mut self, from: (u8, u8), to: (u8, u8), effects: &Vec<Effect>) {
+        if self.rules.crazyhouse {
+            self.pocket_captures(from, to, effects);
+        }
+        self.just_move(from, to);
+        for e in effects {
+            match e {
+                Effect::Capture(p) => self.capture(position(*p, from)),
+                Effect::Move(p1, p2) => {
+                    self.just_move(position(*p1, from), position(*p2, from));
                 }
             }
-            i += 1;
         }
-        return true;
     }
 
-    /// Get (a copy of) the piece that needs to be promoted.
-    /// If you stick to the default pieces, then there should be no issue.
-    pub fn get_promotion(&self) -> Option<((u8, u8), Piece)> {
-        if self.game_state != GameState::Promote {
-            return None;
-        }
-        println!("Looking for a piece to promote.");
-        let row: u8 = 7;
-        for col in 0..8 as u8 {
-            if let Some(p) = self.get_piece_at(col, row) {
-                if p.can_promote && p.color == Color::White {
-                    return Some(((col, row), p.clone()));
-                }
+    /// Puts everything the move captures into the mover's hand.
+    /// Promoted pieces turn back into pawns, and crucial pieces are not kept at all.
+    fn pocket_captures(&mut self, from: (u8, u8), to: (u8, u8), effects: &[Effect]) {
+        let mover = match self.get_piece_at(from.0, from.1) {
+            Some(p) => p.color,
+            None => return,
+        };
+
+        let mut squares = vec![to];
+        for e in effects {
+            if let Effect::Capture(p) = e {
+                squares.push(position(*p, from));
             }
         }
 
-        let row: u8 = 0;
-        for col in 0..8 as u8 {
+        for (col, row) in squares {
             if let Some(p) = self.get_piece_at(col, row) {
-                if p.can_promote && p.color == Color::Black {
-                    return Some(((col, row), p.clone()));
+                if p.color == mover || p.is_crucial {
+                    continue;
                 }
+                let rank = if p.promoted { 'p' } else { p.rank };
+                self.hands[mover.index()].push(rank);
             }
         }
-        None
     }
 
-    /// Returns false when the promotion failed.\
-    /// Will change the state when you are done.\
-    /// You can try promoting pieces not returned by get_promotion, but it will probably fail.
-    pub fn promote(&mut self, pos: (u8, u8), rank: char) -> bool {
-        if self.game_state != GameState::Promote {
+    /// The ranks `color` has in its hand, in the order they were captured. Only used in crazyhouse.
+    pub fn hand(&self, color: Color) -> Vec<char> {
+        self.hands[color.index()].clone()
+    }
+
+    /// Takes a piece of that rank from the turn owner's hand and puts it on the empty square,
+    /// instead of making a move. Pawns can not be dropped on the first or last row.\
+    /// Returns false if crazyhouse is off, or the drop is not allowed.
+    pub fn drop(&mut self, rank: char, to: (u8, u8)) -> bool {
+        if !self.can_drop(rank, to) {
             return false;
         }
 
-        if let Some(p) = self.get_piece_at(pos.0, pos.1) {
-            if !p.can_promote || p.rank == rank {
-                return false;
-            }
+        let hand = &mut self.hands[self.turn_owner.index()];
+        let i = hand.iter().position(|&r| r == rank).unwrap();
+        hand.remove(i);
 
-            if (!(p.color == Color::White && pos.1 == 7)
-                && (!(p.color == Color::Black && pos.1 == 0)))
-            {
-                return false;
-            }
+        self.put(to.0, to.1, Some(self.dropped_piece(rank)));
+        self.reversible.clear();
+        self.halfmove_clock = 0;
+        self.last_move = None;
+        self.increment_turn();
+        true
+    }
 
-            let template_piece = Piece::new(p.color, rank);
+    fn can_drop(&self, rank: char, to: (u8, u8)) -> bool {
+        if !self.rules.crazyhouse
+            || !matches!(self.game_state, GameState::Running | GameState::Check)
+            || !self.hands[self.turn_owner.index()].contains(&rank)
+            || to.0 >= BOARD_SIZE
+            || to.1 >= BOARD_SIZE
+            || self.get_piece_at(to.0, to.1).is_some()
+            || (rank == 'p' && (to.1 == 0 || to.1 == BOARD_SIZE - 1))
+        {
+            return false;
+        }
 
-            if template_piece.is_crucial || template_piece.can_promote {
-                return false;
-            }
+        // Not allowed to leave your own king in check.
+        let mut g = self.clone();
+        g.put(to.0, to.1, Some(self.dropped_piece(rank)));
+        !g.in_check()
+    }
 
-            let promoted_piece = Piece {
-                last_moved: p.last_moved,
-                times_moved: p.times_moved,
-                ..template_piece
-            };
+    /// Dropped pieces count as having moved, so a dropped rook can not castle.
+    /// Dropped pawns still get their double step.
+    fn dropped_piece(&self, rank: char) -> Piece {
+        Piece {
+            last_moved: Some(self.turn_count),
+            times_moved: if rank == 'p' { 0 } else { 1 },
+            ..Piece::new(self.turn_owner, rank)
+        }
+    }
 
-            self.board[(pos.0 + pos.1 * 8) as usize] = Some(promoted_piece);
+    /// Checks if the turn owner has any drop they could make.
+    fn has_drops(&self) -> bool {
+        let mut ranks = self.hands[self.turn_owner.index()].clone();
+        ranks.sort();
+        ranks.dedup();
+        ranks
+            .iter()
+            .any(|&r| (0..SQUARES as u8).any(|i| self.can_drop(r, unpack_square(i))))
+    }
 
-            if self.get_promotion().is_none() {
-                self.game_state = GameState::Running;
+    /// Keeps track of `reversible_history`, `halfmove_clock` and `last_move`. Call it before the move is made.\
+    /// Captures, pawn moves, and the first move of a king or rook (since it loses the right to castle)
+    /// can not be undone, so they clear it.
+    fn record_move(&mut self, from: (u8, u8), to: (u8, u8), effects: &[Effect]) {
+        let piece = match self.get_piece_at(from.0, from.1) {
+            Some(p) => p,
+            None => return,
+        };
 
-                self.increment_turn();
-            }
+        let capture = self.get_piece_at(to.0, to.1).is_some()
+            || effects.iter().any(|e| matches!(e, Effect::Capture(_)));
+        let castling_rights = piece.times_moved == 0 && (piece.rank == 'K' || piece.rank == 'R');
+        let pawn = piece.rank == 'p';
 
-            true
+        if capture || pawn || castling_rights {
+            self.reversible.clear();
         } else {
-            false
+            self.reversible.push(ChessMove {
+                from,
+                to,
+                promotion: None,
+            });
+        }
+
+        if capture || pawn {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
         }
+        self.last_move = Some(ChessMove {
+            from,
+            to,
+            promotion: None,
+        });
     }
 
-    /// Check what the state of the game is.
-    pub fn get_game_state(&self) -> GameState {
-        self.game_state
+    /// Half moves since the last capture or pawn move. At 100, either player can claim a draw.\
+    /// Games made with `from_parts` start at 0.
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
     }
 
-    /// Figure out who's turn it is.
-    pub fn get_turn_owner(&self) -> Color {
-        self.turn_owner
+    /// The last move made, with the promotion filled in once it is done.
+    pub fn last_move(&self) -> Option<ChessMove> {
+        self.last_move
+    }
+
+    /// Checks if the turn owner could claim a draw, either by threefold repetition or the fifty move rule.
+    pub fn can_claim_draw(&self) -> bool {
+        self.halfmove_clock >= 100 || self.is_threefold_now()
+    }
+
+    /// Bundles up who's turn it is, the state, the clocks, and the last move.
+    pub fn status(&self) -> GameStatus {
+        GameStatus {
+            turn_owner: self.turn_owner,
+            game_state: self.game_state,
+            turn_count: self.turn_count,
+            halfmove_clock: self.halfmove_clock,
+            in_check: self.in_check(),
+            can_claim_draw: self.can_claim_draw(),
+            last_move: self.last_move,
+        }
+    }
+
+    /// The moves made since the last capture, pawn move, or lost castling right, oldest first.\
+    /// If the game was made with `from_parts`, anything before that is not known.
+    pub fn reversible_history(&self) -> &[ChessMove] {
+        &self.reversible
+    }
+
+    /// This will force pieces to move. If there is no piece to move, nothing happens and it returns false.\
+    /// Broken effects should already have been caught by `check_move`, this is just in case.
+    fn just_move(&mut self, from: (u8, u8), to: (u8, u8)) -> bool {
+        let piece = match self.get_piece_at(from.0, from.1) {
+            Some(p) => p.clone(),
+            None => return false,
+        };
+
+        let piece2 = Piece {
+            last_moved: Some(self.turn_count),
+            times_moved: piece.times_moved + 1,
+            last_distance: from.0.abs_diff(to.0).max(from.1.abs_diff(to.1)),
+            ..piece
+        };
+
+        if piece2.promotes_at(to.0, to.1) {
+            // Promotion time
+            self.game_state = GameState::Promote;
+        }
+
+        self.put(to.0, to.1, Some(piece2));
+        self.put(from.0, from.1, None);
+        true
+    }
+
+    /// Will remove the piece, no questions asked.
+    fn capture(&mut self, pos: (u8, u8)) {
+        self.put(pos.0, pos.1, None);
+    }
+
+    /// Places the piece (or nothing) on the board. Use this instead of touching the board,
+    /// or the attack map will not know that something changed.
+    fn put(&mut self, col: u8, row: u8, piece: Option<Piece>) {
+        self.board[pack_square(col, row) as usize] = piece;
+        self.attacks = Default::default();
+    }
+
+    /// Every position with a piece of that color and rank on it, from (0,0) to (7,7).
+    pub fn find_pieces(&self, color: Color, rank: char) -> Vec<(u8, u8)> {
+        (0..SQUARES as u8)
+            .filter(|&i| {
+                self.board[i as usize]
+                    .as_ref()
+                    .is_some_and(|p| p.color == color && p.rank == rank)
+            })
+            .map(unpack_square)
+            .collect()
+    }
+
+    /// The color of the square itself, not the piece on it. (0,0) is dark, (7,0) is light.
+    pub fn square_color(col: u8, row: u8) -> Color {
+        if (col + row) & 1 == 1 {
+            Color::White
+        } else {
+            Color::Black
+        }
+    }
+
+    /// (0,0) is bottom left. (7,7) is top right.\
+    /// Due to the structure of this thing, the default doc file does not
+    /// include any information about the Piece struct. However, you should
+    /// be able to find all the necessary stuff in the README file.
+    pub fn get_piece_at(&self, col: u8, row: u8) -> Option<&Piece> {
+        if col >= BOARD_SIZE || row >= BOARD_SIZE {
+            return None;
+        }
+        self.board[pack_square(col, row) as usize].as_ref()
+    }
+
+    /// Checks if the piece on the square has ever moved. None if there is no piece there.
+    pub fn has_piece_moved(&self, col: u8, row: u8) -> Option<bool> {
+        self.get_piece_at(col, row).map(|p| p.times_moved > 0)
+    }
+
+    /// Checks if there is nothing on the square. Squares outside the board are not empty, they are not anything.
+    pub fn is_empty(&self, col: u8, row: u8) -> bool {
+        col < BOARD_SIZE && row < BOARD_SIZE && self.get_piece_at(col, row).is_none()
+    }
+
+    /// Checks if there is a piece of the other color than `color` on the square.
+    pub fn is_enemy(&self, col: u8, row: u8, color: Color) -> bool {
+        self.get_piece_at(col, row)
+            .is_some_and(|p| p.color != color)
+    }
+
+    /// Checks if there is a piece of `color` on the square.
+    pub fn is_friend(&self, col: u8, row: u8, color: Color) -> bool {
+        self.get_piece_at(col, row)
+            .is_some_and(|p| p.color == color)
+    }
+
+    /// Why would you use this? Why did I make this public?
+    pub fn print_board(&self) {
+        print!("{}", self.render(&RenderOptions::default()));
+    }
+
+    /// Why would you use this? Why did I make this public?\
+    /// Black pieces are drawn as <K> here too, like in `print_board`. This one used to draw [K].
+    pub fn print_moves(&self, col: u8, row: u8) {
+        if self.get_piece_at(col, row).is_some() {
+            print!(
+                "{}",
+                self.render(&RenderOptions {
+                    moves_of: Some((col, row)),
+                    ..Default::default()
+                })
+            );
+        }
+    }
+
+    /// The color you give as an argument refers to who the space is safe FOR, not from.
+    pub fn is_safe_position(&self, col: u8, row: u8, color: Color) -> bool {
+        // Assume that every move that they can make in response is safe.
+        // This should (emphasis on should) stop any and all infinite loops.
+        if color != self.turn_owner {
+            return true;
+        }
+
+        self.attack_map(color.opposite()) & (1 << pack_square(col, row)) == 0
+    }
+
+    /// Every square that a piece of `color` could capture something on, as a bit per square
+    /// (bit `col + row * 8`).\
+    /// It is only figured out once per position, so asking again is cheap.
+    pub fn attack_map(&self, color: Color) -> u64 {
+        let index = color.index();
+        if let Some(&map) = self.attacks[index].get() {
+            return map;
+        }
+
+        let mut map = 0_u64;
+        for i in 0..SQUARES as u8 {
+            let (c, r) = unpack_square(i);
+            if let Some(piece) = self.get_piece_at(c, r) {
+                if piece.color != color {
+                    continue;
+                }
+                for square in piece.get_danger_zone(c, r, self) {
+                    map |= 1 << square;
+                }
+            }
+        }
+
+        // Something in there might have asked for the same map already, and got the same answer.
+        let _ = self.attacks[index].set(map);
+        map
+    }
+
+    /// Finds every piece of `color` that could capture something at the position.\
+    /// Unlike `is_safe_position`, this does not care about who's turn it is.\
+    /// Pawns only count if there is something of the other color to capture there,
+    /// so this will not find pieces that defend their own pieces.
+    pub fn attackers_of(&self, col: u8, row: u8, color: Color) -> Vec<(u8, u8)> {
+        let mut all = Vec::new();
+        for i in 0..SQUARES as u8 {
+            let (c, r) = unpack_square(i);
+            if let Some(piece) = self.get_piece_at(c, r) {
+                if piece.color != color {
+                    continue;
+                }
+                if piece
+                    .get_danger_zone(c, r, self)
+                    .contains(&pack_square(col, row))
+                {
+                    all.push(unpack_square(i));
+                }
+            }
+        }
+        all
+    }
+
+    /// Every square `color`'s pawns attack, whether something stands there or not.\
+    /// Worked out straight from where the pawns are and which way they face, so it is a lot faster
+    /// than `attackers_of`, and en passant is not part of it.
+    pub fn pawn_attacks(&self, color: Color) -> HashSet<(u8, u8)> {
+        let mut all = HashSet::new();
+        for (i, p) in self.board.iter().enumerate() {
+            let p = match p {
+                Some(p) if p.rank == 'p' && p.color == color => p,
+                _ => continue,
+            };
+            let (col, row) = unpack_square(i as u8);
+            // One step forward and one to either side, like in `new_pawn_facing`.
+            let side = (p.forward.1.abs(), p.forward.0.abs());
+            for sign in [1, -1] {
+                let c = col as i8 + p.forward.0 + side.0 * sign;
+                let r = row as i8 + p.forward.1 + side.1 * sign;
+                if (0..BOARD_SIZE as i8).contains(&c) && (0..BOARD_SIZE as i8).contains(&r) {
+                    all.insert((c as u8, r as u8));
+                }
+            }
+        }
+        all
+    }
+
+    /// Every square the turn owner could legally capture the piece at the position from, sorted.\
+    /// Unlike `attackers_of`, pinned pieces are left out, and en passant counts.
+    pub fn captures_of_square(&self, col: u8, row: u8) -> Vec<(u8, u8)> {
+        let mut all = Vec::new();
+        if self.get_piece_at(col, row).is_none() {
+            return all;
+        }
+
+        // Either it lands there, or one of its effects captures there.
+        let hits = |from: (u8, u8), t: u8, effects: &[Effect]| {
+            unpack_square(t) == (col, row)
+                || effects
+                    .iter()
+                    .any(|e| matches!(e, Effect::Capture(p) if position(*p, from) == (col, row)))
+        };
+
+        for i in 0..SQUARES as u8 {
+            let from = unpack_square(i);
+            if let Some(p) = self.get_piece_at(from.0, from.1) {
+                if p.color == self.turn_owner
+                    && p.get_all_possible_moves(from.0, from.1, self)
+                        .iter()
+                        .any(|(&t, effects)| hits(from, t, effects))
+                {
+                    all.push(from);
+                }
+            }
+        }
+        all
+    }
+
+    /// Every (attacker, victim) pair where a piece of the other color could capture a piece of `color`,
+    /// sorted by victim and then attacker. Crucial pieces count, so a check shows up here too.
+    pub fn threats_to(&self, color: Color) -> Vec<((u8, u8), (u8, u8))> {
+        let mut all = Vec::new();
+        for i in 0..SQUARES as u8 {
+            let (col, row) = unpack_square(i);
+            match self.get_piece_at(col, row) {
+                Some(p) if p.color == color => {
+                    for attacker in self.attackers_of(col, row, color.opposite()) {
+                        all.push((attacker, (col, row)));
+                    }
+                }
+                _ => {}
+            }
+        }
+        all
+    }
+
+    /// Checks if a piece of `color` could capture on the position if an enemy stood there.
+    /// That is, if it defends its own piece there (or the empty square).
+    pub fn is_defended(&self, col: u8, row: u8, color: Color) -> bool {
+        !self.defenders_of(col, row, color).is_empty()
+    }
+
+    /// Checks if `by` would attack `sq` after the move, like `is_defended` does.
+    /// Promotions are counted as becoming a queen. None if the move can not be made,
+    /// or if something would have to promote and can not become a queen.\
+    /// Good for finding out if a king could walk there afterwards.
+    pub fn square_attacked_after(
+        &self,
+        from: (u8, u8),
+        to: (u8, u8),
+        sq: (u8, u8),
+        by: Color,
+    ) -> Option<bool> {
+        self.after_move(from, to)
+            .map(|g| g.is_defended(sq.0, sq.1, by))
+    }
+
+    /// How many more pieces the other side has covering the square than `color` does.
+    /// Positive means `color` has lost control of it, negative means `color` controls it.\
+    /// Both sides are counted like `is_defended`, so it does not matter what stands there.
+    pub fn control(&self, col: u8, row: u8, color: Color) -> i32 {
+        self.defenders_of(col, row, color.opposite()).len() as i32
+            - self.defenders_of(col, row, color).len() as i32
+    }
+
+    /// Every piece of `color` that could capture on the position if an enemy stood there.
+    fn defenders_of(&self, col: u8, row: u8, color: Color) -> Vec<(u8, u8)> {
+        if col >= BOARD_SIZE || row >= BOARD_SIZE {
+            return Vec::new();
+        }
+        // Pieces can not capture their friends, so swap in an enemy and see who can take it.
+        let mut g = self.clone();
+        g.put(col, row, Some(Piece::new(color.opposite(), 'p')));
+        g.attackers_of(col, row, color)
+    }
+
+    /// Finds what the piece at the position would attack if the first piece in its way was not there.\
+    /// Both the blocking piece and whatever stands behind it are included.
+    /// Only moves that can capture count, and requirements are ignored.
+    pub fn xray_attacks(&self, col: u8, row: u8) -> HashSet<u8> {
+        let mut all = HashSet::new();
+        let piece = match self.get_piece_at(col, row) {
+            Some(p) => p,
+            None => return all,
+        };
+
+        for m in piece.moves.iter().filter(|m| m.can_capture) {
+            let max_s = m.maximum_slide.unwrap_or(BOARD_SIZE);
+            for (d_col, d_row) in m.all_directions() {
+                let mut blockers = 0;
+                for i in 1..=max_s as i8 {
+                    let c = col as i8 + i * d_col;
+                    let r = row as i8 + i * d_row;
+                    if !(0..BOARD_SIZE as i8).contains(&c) || !(0..BOARD_SIZE as i8).contains(&r) {
+                        break;
+                    }
+                    if i as u8 >= m.minimum_slide {
+                        all.insert(pack_square(c as u8, r as u8));
+                    }
+                    if self.get_piece_at(c as u8, r as u8).is_some() {
+                        blockers += 1;
+                        if blockers == 2 {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        all
+    }
+
+    /// The squares on the straight or diagonal line from `from` to `to`, both included, in that order.\
+    /// None if they are not on the same line, the same square, or outside the board.
+    /// Good for drawing checks and pins as lines.
+    pub fn ray_between(&self, from: (u8, u8), to: (u8, u8)) -> Option<Vec<(u8, u8)>> {
+        if from == to
+            || from.0 >= BOARD_SIZE
+            || from.1 >= BOARD_SIZE
+            || to.0 >= BOARD_SIZE
+            || to.1 >= BOARD_SIZE
+        {
+            return None;
+        }
+        let d_col = to.0 as i8 - from.0 as i8;
+        let d_row = to.1 as i8 - from.1 as i8;
+        if d_col != 0 && d_row != 0 && d_col.abs() != d_row.abs() {
+            return None;
+        }
+
+        let steps = d_col.abs().max(d_row.abs());
+        Some(
+            (0..=steps)
+                .map(|i| {
+                    (
+                        (from.0 as i8 + i * d_col.signum()) as u8,
+                        (from.1 as i8 + i * d_row.signum()) as u8,
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// When the turn owner is in check, a piece that is not the king can only help by capturing
+    /// the checker or getting in its way. This gives those squares, so every other move can be
+    /// skipped without trying it out. None means there is nothing to skip.\
+    /// It only ever rules out moves that would fail `is_safe_move` anyway.
+    fn evasion_mask(&self, piece: &Piece) -> Option<u64> {
+        if self.rules.capture_the_king || piece.is_crucial || piece.color != self.turn_owner {
+            return None;
+        }
+
+        let crucial: Vec<(u8, u8)> = (0..SQUARES as u8)
+            .map(unpack_square)
+            .filter(|&(c, r)| {
+                self.get_piece_at(c, r)
+                    .is_some_and(|p| p.is_crucial && p.color == piece.color)
+            })
+            .collect();
+        let king = match crucial[..] {
+            [king] => king,
+            _ => return None,
+        };
+        // The attack map is cached, so this is cheap when there is no check.
+        if self.is_safe_position(king.0, king.1, piece.color) {
+            return None;
+        }
+        // A cannon-style check can also be escaped by moving its screen out of the way.
+        if self
+            .board
+            .iter()
+            .flatten()
+            .any(|p| p.moves.iter().any(|m| m.screens.is_some()))
+        {
+            return None;
+        }
+
+        let checkers = self.attackers_of(king.0, king.1, piece.color.opposite());
+        let checker = match checkers[..] {
+            [checker] => checker,
+            // Only the king can get out of a double check.
+            [_, _, ..] => return Some(0),
+            [] => return None,
+        };
+
+        let mut mask = 1_u64 << pack_square(checker.0, checker.1);
+        if let Some(ray) = self.ray_between(checker, king) {
+            for (c, r) in ray {
+                mask |= 1 << pack_square(c, r);
+            }
+        } else if gcd(king.0.abs_diff(checker.0), king.1.abs_diff(checker.1)) > 1 {
+            // Some custom piece that slides in a weird direction, so it could be blocked somewhere odd.
+            return None;
+        }
+        Some(mask)
+    }
+
+    /// Checks if nothing the piece does (without effects) can put its own crucial pieces in danger,
+    /// so `is_safe_move` can be skipped for those moves. That is when nothing is in check right now,
+    /// and taking the piece off the board would not change that, meaning it is not pinned.
+    fn can_skip_safety(&self, piece: &Piece, col: u8, row: u8) -> bool {
+        if self.rules.capture_the_king || piece.is_crucial || piece.color != self.turn_owner {
+            return false;
+        }
+        if self.in_check() {
+            return false;
+        }
+        // Landing in front of a cannon-style piece can give it something to jump over, so no shortcuts.
+        if self
+            .board
+            .iter()
+            .flatten()
+            .any(|p| p.moves.iter().any(|m| m.screens.is_some()))
+        {
+            return false;
+        }
+
+        let mut g = self.clone();
+        g.put(col, row, None);
+        !g.in_check()
+    }
+
+    /// The color you give as an argument refers to who the space is safe FOR, not from.
+    fn is_safe_move(
+        &self,
+        from: (u8, u8),
+        to: (u8, u8),
+        effects: &Vec<Effect>,
+        color: Color,
+    ) -> bool {
+        // Anything goes, you just lose if they take it.
+        if self.rules.capture_the_king {
+            return true;
+        }
+
+        let mut gc = self.clone();
+        gc.just_execute_move(from, to, effects);
+
+        for (i, p) in gc.board.iter().enumerate() {
+            let i = i as u8;
+            if let Some(piece) = p {
+                let (c, r) = unpack_square(i);
+                if piece.is_crucial && piece.color == color && !gc.is_safe_position(c, r, color) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Get (a copy of) the piece that needs to be promoted.
+    /// If you stick to the default pieces, then there should be no issue.
+    pub fn get_promotion(&self) -> Option<((u8, u8), Piece)> {
+        if self.game_state != GameState::Promote {
+            return None;
+        }
+        println!("Looking for a piece to promote.");
+        let (col, row) = self.promotion_square()?;
+        self.get_piece_at(col, row).map(|p| ((col, row), p.clone()))
+    }
+
+    /// Same as `get_promotion`, but quietly, and only the position.
+    pub fn promotion_square(&self) -> Option<(u8, u8)> {
+        if self.game_state != GameState::Promote {
+            return None;
+        }
+        for i in 0..SQUARES as u8 {
+            let (c, r) = unpack_square(i);
+            if let Some(p) = self.get_piece_at(c, r) {
+                if p.promotes_at(c, r) {
+                    return Some(unpack_square(i));
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns false when the promotion failed.\
+    /// Will change the state when you are done.\
+    /// You can try promoting pieces not returned by get_promotion, but it will probably fail.
+    pub fn promote(&mut self, pos: (u8, u8), rank: char) -> bool {
+        if self.game_state != GameState::Promote {
+            return false;
+        }
+
+        if let Some(p) = self.get_piece_at(pos.0, pos.1) {
+            if !p.promotes_at(pos.0, pos.1) || !p.promotion_ranks().contains(&rank) {
+                return false;
+            }
+
+            let color = p
+                .promotion
+                .as_ref()
+                .and_then(|r| r.color)
+                .unwrap_or(p.color);
+            let template_piece = Piece::new(color, rank);
+
+            let promoted_piece = Piece {
+                last_moved: p.last_moved,
+                times_moved: p.times_moved,
+                last_distance: p.last_distance,
+                promoted: true,
+                ..template_piece
+            };
+
+            self.put(pos.0, pos.1, Some(promoted_piece));
+            if let Some(m) = self.last_move.as_mut().filter(|m| m.to == pos) {
+                m.promotion = Some(rank);
+            }
+
+            if self.promotion_square().is_none() {
+                self.game_state = GameState::Running;
+
+                self.increment_turn();
+            }
+
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Flips the board upside down and swaps the colors of every piece, and who's turn it is.
+    /// Anything White could do, Black can now do on the other side, and the other way around.
+    pub fn mirror_vertical(&self) -> Game {
+        let mut board: [Option<Piece>; SQUARES] = std::array::from_fn(|_| None);
+
+        for i in 0..SQUARES as u8 {
+            let (c, r) = unpack_square(i);
+            if let Some(p) = self.get_piece_at(c, r) {
+                board[pack_square(c, BOARD_SIZE - 1 - r) as usize] = Some(p.mirrored());
+            }
+        }
+
+        // Black's moves come after White's in the same turn,
+        // so the turn count has to move for the last move to still count as the last one.
+        let (turn_owner, turn_count) = match self.turn_owner {
+            Color::White => (Color::Black, self.turn_count.saturating_sub(1)),
+            Color::Black => (Color::White, self.turn_count + 1),
+        };
+
+        let mut g = Game {
+            board,
+            turn_owner,
+            turn_count,
+            game_state: self.game_state,
+            attacks: Default::default(),
+            rules: self.rules,
+            history: Vec::new(),
+            reversible: Vec::new(),
+            hands: [self.hands[1].clone(), self.hands[0].clone()],
+            halfmove_clock: self.halfmove_clock,
+            last_move: None,
+        };
+        g.history.push(g.position_key());
+        g
+    }
+
+    /// The board as characters, using the same layout as the `make_board` template.\
+    /// Unlike the template, the case tells you the color: White is upper case (so pawns are 'P')
+    /// and Black is lower case. Empty squares are '0'.
+    pub fn to_char_array(&self) -> [char; SQUARES] {
+        let mut chars = ['0'; SQUARES];
+        for (i, square) in self.board.iter().enumerate() {
+            if let Some(p) = square {
+                chars[i] = match p.color {
+                    Color::White => p.rank.to_ascii_uppercase(),
+                    Color::Black => p.rank.to_ascii_lowercase(),
+                };
+            }
+        }
+        chars
+    }
+
+    /// How many pieces of each (color, rank) there are on the board. Ranks with no pieces are left out.
+    pub fn material(&self) -> HashMap<(Color, char), u8> {
+        let mut all = HashMap::new();
+        for p in self.board.iter().flatten() {
+            *all.entry((p.color, p.rank)).or_insert(0) += 1;
+        }
+        all
+    }
+
+    /// The pieces on the board written like "KRvK", White first. Good for looking up endgames.\
+    /// Crucial pieces come first, then everything else from most to least valuable. Pawns are 'P'.
+    pub fn material_signature(&self) -> String {
+        let side = |color: Color| {
+            let mut pieces: Vec<&Piece> = self
+                .board
+                .iter()
+                .flatten()
+                .filter(|p| p.color == color)
+                .collect();
+            // Bishops and knights are worth the same, but bishops are usually written first.
+            pieces.sort_by_key(|p| (!p.is_crucial, -p.value(), p.rank != 'B'));
+            pieces
+                .iter()
+                .map(|p| p.rank.to_ascii_uppercase())
+                .collect::<String>()
+        };
+        format!("{}v{}", side(Color::White), side(Color::Black))
+    }
+
+    /// Lists every square where the two games differ, as (col, row, rank here, rank in other).\
+    /// A square also counts as changed if the rank stayed the same but the color did not.
+    pub fn diff(&self, other: &Game) -> Vec<(u8, u8, Option<char>, Option<char>)> {
+        let mut changes = Vec::new();
+        for i in 0..SQUARES as u8 {
+            let (c, r) = unpack_square(i);
+            let old = self.get_piece_at(c, r);
+            let new = other.get_piece_at(c, r);
+            if old.map(|p| (p.rank, p.color)) != new.map(|p| (p.rank, p.color)) {
+                changes.push((c, r, old.map(|p| p.rank), new.map(|p| p.rank)));
+            }
+        }
+        changes
+    }
+
+    /// Turns rules on or off. The game state is figured out again,
+    /// since taking away moves might leave someone without any.
+    pub fn set_rules(&mut self, rules: RuleSet) {
+        self.rules = rules;
+        self.attacks = Default::default();
+        if self.game_state != GameState::Promote {
+            self.compute_state();
+        }
+    }
+
+    /// Gives the turn to `color`, in case you set up a puzzle and picked the wrong side.
+    /// The game state is figured out again for the new turn owner.
+    pub fn set_turn(&mut self, color: Color) {
+        self.turn_owner = color;
+        self.attacks = Default::default();
+        if self.game_state != GameState::Promote {
+            self.compute_state();
+        }
+        // It is the same position, just with someone else to move.
+        self.history.pop();
+        self.history.push(self.position_key());
+    }
+
+    /// Check which rules are in play.
+    pub fn get_rules(&self) -> RuleSet {
+        self.rules
+    }
+
+    /// Check what the state of the game is.
+    pub fn get_game_state(&self) -> GameState {
+        self.game_state
+    }
+
+    /// Figure out who's turn it is.
+    pub fn get_turn_owner(&self) -> Color {
+        self.turn_owner
     }
 
     /// Gets a hashmap of all possible moves a piece at the specified
@@ -451,466 +1557,4174 @@ impl Game {
     /// Since the positions are the "keys", I recommend that
     /// you iterate across the whole thing.\
     /// By the way, if you want to actually get a position from the
-    /// singe u8 this thing gives you, do the following
-    /// ```
+    /// single u8 this thing gives you, use `unpack_square`
+    /// ```ignore
     /// let pos = // Imagine that it somehow gets a position from the hashmap.
-    /// let col = pos % 8;
-    /// let row = pose >> 3;
+    /// let (col, row) = unpack_square(pos);
     /// ```
     /// Quick side note; the engine caches no results, ever. So, consider caching it yourself.
     pub fn get_moves(&self, col: u8, row: u8) -> Option<HashMap<u8, Vec<Effect>>> {
         if let Some(p) = self.get_piece_at(col, row) {
             return Some(p.get_all_possible_moves(col, row, self));
         }
-        None
+        None
+    }
+
+    /// Checks if the move can be made, and would need a promotion afterwards.
+    /// Good for knowing when to pop up the promotion menu.
+    pub fn is_promotion_move(&self, from: (u8, u8), to: (u8, u8)) -> bool {
+        match self.get_piece_at(from.0, from.1) {
+            Some(p) if p.color == self.turn_owner && p.promotes_at(to.0, to.1) => p
+                .get_all_possible_moves(from.0, from.1, self)
+                .contains_key(&pack_square(to.0, to.1)),
+            _ => false,
+        }
+    }
+
+    /// The command of the move that takes the piece from `from` to `to`, like "O-O" for castling.\
+    /// None if the move has no command, or can not be made.
+    pub fn move_command(&self, from: (u8, u8), to: (u8, u8)) -> Option<String> {
+        let piece = self.get_piece_at(from.0, from.1)?;
+        piece
+            .moves_with_source(from.0, from.1, self)
+            .into_iter()
+            .filter(|&(sq, _)| sq == pack_square(to.0, to.1))
+            .find_map(|(_, i)| piece.moves[i].command.clone())
+    }
+
+    /// Checks if the turn owner is in check right now, without trusting the game state.
+    pub fn is_check(&self) -> bool {
+        self.in_check()
+    }
+
+    /// The moves of the piece at the position, split into (quiet moves, captures), sorted like `legal_moves`.\
+    /// En passant counts as a capture, even though nothing stands where the pawn lands.
+    #[allow(clippy::type_complexity)]
+    pub fn categorized_moves(&self, col: u8, row: u8) -> (Vec<(u8, u8)>, Vec<(u8, u8)>) {
+        let mut moves: Vec<(u8, Vec<Effect>)> = self
+            .get_moves(col, row)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        moves.sort_by_key(|(t, _)| *t);
+
+        let (mut quiet, mut captures) = (Vec::new(), Vec::new());
+        for (t, effects) in moves {
+            let to = unpack_square(t);
+            if self.get_piece_at(to.0, to.1).is_some()
+                || effects.iter().any(|e| matches!(e, Effect::Capture(_)))
+            {
+                captures.push(to);
+            } else {
+                quiet.push(to);
+            }
+        }
+        (quiet, captures)
+    }
+
+    /// Tells you which piece a move would capture, as (col, row, rank).\
+    /// This is usually the piece on `to`, but en passant captures a piece somewhere else.
+    /// Returns None for moves that capture nothing, and for moves that can not be made.
+    pub fn capture_target(&self, from: (u8, u8), to: (u8, u8)) -> Option<(u8, u8, char)> {
+        let moves = self.get_moves(from.0, from.1)?;
+        let effects = moves.get(&pack_square(to.0, to.1))?;
+
+        if let Some(p) = self.get_piece_at(to.0, to.1) {
+            return Some((to.0, to.1, p.rank));
+        }
+
+        for e in effects {
+            if let Effect::Capture(p) = e {
+                let (col, row) = position(*p, from);
+                if let Some(p) = self.get_piece_at(col, row) {
+                    return Some((col, row, p.rank));
+                }
+            }
+        }
+        None
+    }
+
+    /// Every move the turn owner can make right now, as (from, to) pairs.\
+    /// Pieces are gone through from (0,0) to (7,7), and so are their targets.
+    pub fn legal_moves(&self) -> Vec<((u8, u8), (u8, u8))> {
+        self.legal_moves_iter().collect()
+    }
+
+    /// The same moves as `legal_moves`, but grouped by piece, as (square, rank, targets).\
+    /// Pieces that can not move are left out, so every group has at least one target.
+    #[allow(clippy::type_complexity)]
+    pub fn moves_grouped(&self) -> Vec<((u8, u8), char, Vec<(u8, u8)>)> {
+        let mut groups: Vec<((u8, u8), char, Vec<(u8, u8)>)> = Vec::new();
+        for (from, to) in self.legal_moves_iter() {
+            match groups.last_mut() {
+                Some((sq, _, targets)) if *sq == from => targets.push(to),
+                _ => {
+                    let rank = self.get_piece_at(from.0, from.1).unwrap().rank;
+                    groups.push((from, rank, vec![to]));
+                }
+            }
+        }
+        groups
+    }
+
+    /// Same as `legal_moves`, but sorted as tuples, so by the column of `from`, then its row, then `to`.\
+    /// It is the order you get if you sort the moves yourself, so use it when two runs have to agree.
+    pub fn legal_moves_sorted(&self) -> Vec<((u8, u8), (u8, u8))> {
+        let mut moves = self.legal_moves();
+        moves.sort();
+        moves
+    }
+
+    /// Same as `legal_moves`, but the moves of a piece are only generated when you get to it,
+    /// so you can stop early without paying for the rest.
+    pub fn legal_moves_iter(&self) -> impl Iterator<Item = ((u8, u8), (u8, u8))> + '_ {
+        (0..SQUARES as u8).flat_map(move |i| {
+            let mut targets = Vec::new();
+            let (c, r) = unpack_square(i);
+            if let Some(p) = self.get_piece_at(c, r) {
+                if p.color == self.turn_owner {
+                    targets = p.get_all_possible_moves(c, r, self).into_keys().collect();
+                    targets.sort();
+                }
+            }
+            targets
+                .into_iter()
+                .map(move |t| (unpack_square(i), unpack_square(t)))
+        })
+    }
+
+    /// Checks if making the move would put the other player in check (or check mate).\
+    /// Promotions are counted as becoming a queen. Returns false if the move can not be made.
+    pub fn gives_check(&self, from: (u8, u8), to: (u8, u8)) -> bool {
+        self.after_move(from, to).is_some_and(|g| g.in_check())
+    }
+
+    /// Every move the turn owner can make that gives check, in the same order as `legal_moves`.
+    pub fn checking_moves(&self) -> Vec<((u8, u8), (u8, u8))> {
+        self.legal_moves_iter()
+            .filter(|&(from, to)| self.gives_check(from, to))
+            .collect()
+    }
+
+    /// How many moves the turn owner has, without making a list of them.
+    /// 0 means check mate or stalemate.
+    pub fn legal_move_count(&self) -> usize {
+        self.legal_moves_iter().count()
+    }
+
+    /// If the turn owner has exactly one move, then this is it.
+    pub fn forced_move(&self) -> Option<((u8, u8), (u8, u8))> {
+        let mut moves = self.legal_moves_iter();
+        let first = moves.next()?;
+        if moves.next().is_some() {
+            return None;
+        }
+        Some(first)
+    }
+
+    /// Same as `legal_moves`, but as `ChessMove`s.
+    /// Moves that promote show up once for every rank the piece can promote to.
+    pub fn legal_chess_moves(&self) -> Vec<ChessMove> {
+        (0..SQUARES as u8)
+            .map(unpack_square)
+            .flat_map(|(c, r)| self.legal_moves_from(c, r))
+            .collect()
+    }
+
+    /// Every move the piece at the position can make, as `ChessMove`s sorted by target.
+    /// Moves that promote show up once for every rank the piece can promote to.\
+    /// Empty if there is no piece there, or if it is not that piece's turn.
+    pub fn legal_moves_from(&self, col: u8, row: u8) -> Vec<ChessMove> {
+        let piece = match self.get_piece_at(col, row) {
+            Some(p) if p.color == self.turn_owner => p,
+            _ => return Vec::new(),
+        };
+
+        let mut targets: Vec<u8> = piece
+            .get_all_possible_moves(col, row, self)
+            .into_keys()
+            .collect();
+        targets.sort();
+
+        let mut all = Vec::new();
+        for t in targets {
+            let to = unpack_square(t);
+            if piece.promotes_at(to.0, to.1) {
+                for &rank in piece.promotion_ranks() {
+                    all.push(ChessMove {
+                        from: (col, row),
+                        to,
+                        promotion: Some(rank),
+                    });
+                }
+            } else {
+                all.push(ChessMove {
+                    from: (col, row),
+                    to,
+                    promotion: None,
+                });
+            }
+        }
+        all
+    }
+
+    /// If the king of that color could castle right now, as (king side, queen side).\
+    /// For the player who is not moving, it pretends it is their turn, so check still counts.
+    pub fn can_castle(&self, color: Color) -> (bool, bool) {
+        if color != self.turn_owner {
+            return self.with_turn_owner(color).can_castle(color);
+        }
+
+        for i in 0..SQUARES as u8 {
+            let (col, row) = unpack_square(i);
+            match self.get_piece_at(col, row) {
+                Some(p) if p.rank == 'K' && p.color == color => {
+                    let moves = p.get_all_possible_moves(col, row, self);
+                    let side = |d: i8| {
+                        let c = col as i8 + d;
+                        (0..BOARD_SIZE as i8).contains(&c)
+                            && moves.contains_key(&pack_square(c as u8, row))
+                    };
+                    return (side(2), side(-2));
+                }
+                _ => {}
+            }
+        }
+        (false, false)
+    }
+
+    /// Adds up the value of every piece, white minus black.
+    pub fn material_balance(&self) -> i32 {
+        let mut sum = 0;
+        for p in self.board.iter().flatten() {
+            match p.color {
+                Color::White => sum += p.value(),
+                Color::Black => sum -= p.value(),
+            }
+        }
+        sum
+    }
+
+    /// Checks that the player who just moved is not in check, since they would not have been
+    /// allowed to make that move. Useful if you got the position from somewhere you do not trust.
+    pub fn is_legal_position(&self) -> bool {
+        let attacks = self.attack_map(self.turn_owner);
+        !self.board.iter().enumerate().any(|(i, p)| match p {
+            Some(p) => p.is_crucial && p.color != self.turn_owner && attacks & (1 << i) != 0,
+            None => false,
+        })
+    }
+
+    /// Checks if the turn owner is in check mate right now, without trusting the game state.
+    pub fn is_checkmate(&self) -> bool {
+        self.in_check() && !self.has_moves()
+    }
+
+    /// Checks if the turn owner is stuck without being in check, without trusting the game state.
+    pub fn is_stalemate(&self) -> bool {
+        !self.in_check() && !self.has_moves()
+    }
+
+    /// Like `is_checkmate`, but for any color, so you can check if you just mated the other player.
+    pub fn is_checkmated(&self, color: Color) -> bool {
+        if color == self.turn_owner {
+            return self.is_checkmate();
+        }
+        self.with_turn_owner(color).is_checkmate()
+    }
+
+    /// Like `is_stalemate`, but for any color. Whoever is not to move can still be "stuck",
+    /// which is only really useful for puzzles and variants.
+    pub fn is_stalemated(&self, color: Color) -> bool {
+        if color == self.turn_owner {
+            return self.is_stalemate();
+        }
+        self.with_turn_owner(color).is_stalemate()
+    }
+
+    /// A copy of the game where it is `color`'s turn, without touching the game state or history.
+    fn with_turn_owner(&self, color: Color) -> Game {
+        let mut g = self.clone();
+        g.turn_owner = color;
+        g.attacks = Default::default();
+        g
+    }
+
+    fn has_moves(&self) -> bool {
+        for i in 0..SQUARES as u8 {
+            let (c, r) = unpack_square(i);
+            if let Some(p) = self.get_piece_at(c, r) {
+                if p.color != self.turn_owner {
+                    continue;
+                }
+            } else {
+                continue;
+            }
+
+            if let Some(m) = self.get_moves(c, r) {
+                if !m.is_empty() {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn in_check(&self) -> bool {
+        for i in 0..SQUARES as u8 {
+            let (c, r) = unpack_square(i);
+            if let Some(p) = self.get_piece_at(c, r) {
+                if p.is_crucial
+                    && p.color == self.turn_owner
+                    && !self.is_safe_position(c, r, p.color)
+                {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn increment_turn(&mut self) {
+        if self.turn_owner == Color::Black {
+            self.turn_count += 1;
+        }
+        self.turn_owner = self.turn_owner.opposite();
+        // Some moves care about when pieces last moved, so the attacks can change with the turn.
+        self.attacks = Default::default();
+
+        self.compute_state();
+        self.history.push(self.position_key());
+    }
+
+    /// What counts as the same position when looking for repetitions.
+    fn position_key(&self) -> PositionKey {
+        (
+            self.to_char_array(),
+            self.turn_owner,
+            self.castling_rights(),
+            self.en_passant_square(),
+            self.sorted_hands(),
+        )
+    }
+
+    /// The hands with the ranks in order, since it does not matter what got captured first.
+    fn sorted_hands(&self) -> [Vec<char>; 2] {
+        let mut hands = self.hands.clone();
+        for hand in &mut hands {
+            hand.sort_unstable();
+        }
+        hands
+    }
+
+    /// Every rook that can still castle, as a bit per square. That is an unmoved rook in a corner,
+    /// on the home row of an unmoved king of the same color.\
+    /// Castling might be blocked right now, but the right to do it later is still there.
+    fn castling_rights(&self) -> u64 {
+        let mut rights = 0;
+        for (i, p) in self.board.iter().enumerate() {
+            let king = match p {
+                Some(k) if k.is_crucial && k.rank == 'K' && k.times_moved == 0 => k,
+                _ => continue,
+            };
+            let row = unpack_square(i as u8).1;
+            let home_row = match king.color {
+                Color::White => 0,
+                Color::Black => BOARD_SIZE - 1,
+            };
+            if row != home_row {
+                continue;
+            }
+            for col in [0, BOARD_SIZE - 1] {
+                match self.get_piece_at(col, row) {
+                    Some(r)
+                        if r.rank == 'R'
+                            && r.color == king.color
+                            && r.times_moved == 0
+                            && !r.promoted =>
+                    {
+                        rights |= 1 << pack_square(col, row);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        rights
+    }
+
+    /// The square of the pawn that just made a double step, if the turn owner can actually take it en passant.
+    fn en_passant_square(&self) -> Option<u8> {
+        let (col, row) = self.last_move?.to;
+        match self.get_piece_at(col, row) {
+            Some(p) if p.rank == 'p' && p.last_distance == 2 => {}
+            _ => return None,
+        }
+
+        // Only something right next to it can take it, so only those pieces are asked.
+        for dc in -1..=1_i8 {
+            for dr in -1..=1_i8 {
+                let c = col as i8 + dc;
+                let r = row as i8 + dr;
+                if !(0..BOARD_SIZE as i8).contains(&c) || !(0..BOARD_SIZE as i8).contains(&r) {
+                    continue;
+                }
+                let from = (c as u8, r as u8);
+                let piece = match self.get_piece_at(from.0, from.1) {
+                    Some(p) if p.color == self.turn_owner => p,
+                    _ => continue,
+                };
+                let takes = piece
+                    .get_all_possible_moves(from.0, from.1, self)
+                    .values()
+                    .flatten()
+                    .any(|e| matches!(e, Effect::Capture(p) if position(*p, from) == (col, row)));
+                if takes {
+                    return Some(pack_square(col, row));
+                }
+            }
+        }
+        None
+    }
+
+    /// True if the current position has been seen at least twice before, with the same player to move.
+    pub fn is_threefold_now(&self) -> bool {
+        let now = self.position_key();
+        self.history.iter().filter(|&k| *k == now).count() >= 3
+    }
+
+    /// Checks if making the move would bring the game to a position seen for the third time,
+    /// so the draw can be claimed together with the move. False if the move can not be made.\
+    /// Promotions are counted as becoming a queen, which is never a repetition anyway.
+    pub fn is_threefold_after(&self, from: (u8, u8), to: (u8, u8)) -> bool {
+        self.after_move(from, to)
+            .is_some_and(|g| g.is_threefold_now())
+    }
+
+    /// Figures out the state of the game from the board and the turn owner.\
+    /// Both checks are only done once, and the check comes from the attack map.
+    fn compute_state(&mut self) {
+        if self.rules.capture_the_king {
+            let alive = self
+                .board
+                .iter()
+                .flatten()
+                .any(|p| p.is_crucial && p.color == self.turn_owner);
+
+            self.game_state = if !alive {
+                GameState::CrucialCaptured
+            } else if self.has_moves() {
+                GameState::Running
+            } else {
+                self.stalemate()
+            };
+            return;
+        }
+
+        let check = self.in_check();
+        let moves = self.has_moves() || (self.rules.crazyhouse && self.has_drops());
+
+        self.game_state = match (check, moves) {
+            (false, true) => GameState::Running,
+            (true, true) => GameState::Check,
+            (true, false) => GameState::CheckMate,
+            (false, false) => self.stalemate(),
+        };
+    }
+
+    /// What having no moves means, with the current rules.
+    fn stalemate(&self) -> GameState {
+        if self.rules.stalemate_wins {
+            GameState::StalemateWin
+        } else {
+            GameState::Stalemate
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const TEST_TEMPLATE: [char; 64] = [
+        '0', 'K', '0', '0', '0', '0', '0', '0', //
+        '0', '0', '0', '0', '0', '0', '0', '0', //
+        '0', '0', '0', '0', '0', '0', '0', '0', //
+        '0', '0', '0', '0', '0', '0', '0', '0', //
+        '0', '0', '0', '0', '0', '0', '0', '0', //
+        '0', '0', '0', '0', '0', '0', '0', '0', //
+        '0', '0', '0', '0', '0', '0', '0', '0', //
+        '0', '0', '0', '0', '0', '0', 'K', '0', //
+    ];
+    const COLOR_TEMPLATE: u64 = 0x00000000FFFFFFFF;
+    #[test]
+    fn make_and_print() {
+        let g = Game::new();
+        g.print_board();
+    }
+
+    #[test]
+    fn display_moves() {
+        let g = Game::new();
+        g.print_moves(4, 1);
+        g.print_moves(5, 1);
+    }
+
+    #[test]
+    fn danger_zone() {
+        let g = Game::new();
+
+        for (i, o) in g.board.iter().enumerate() {
+            let i = i as u8;
+            if let Some(p) = o {
+                println!("Testing {} at {},{}", p.rank, i % 8, i >> 3);
+                p.get_danger_zone(i % 8, i >> 3, &g);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pawn_move_normal() {
+        let from = (4_u8, 1_u8);
+        let to = (4_u8, 2_u8);
+
+        let mut g = Game::new();
+        let p = g.get_piece_at(from.0, from.1).unwrap();
+        let m = p.moves[0].prune(&g, from);
+
+        if m.len() != 1 || !m.contains_key(&(to.0 + to.1 * 8)) {
+            panic!();
+        }
+
+        println!("Move success: {}", g.make_move(from, to));
+        let a = g.get_piece_at(to.0, to.1);
+
+        println!("Debug Piece: {:#?}", a);
+        g.print_board();
+
+        if let Some(p) = a {
+            if p.color == Color::White && p.last_moved == Some(1) && p.times_moved == 1 {
+                return;
+            }
+        }
+        panic!();
+    }
+
+    #[test]
+    fn test_pawn_move_double() {
+        let from = (4_u8, 1_u8);
+        let to = (4_u8, 3_u8);
+        let to2 = (4_u8, 5_u8);
+
+        let mut g = Game::new();
+        let p = g.get_piece_at(from.0, from.1).unwrap();
+        let m = p.moves[1].prune(&g, from);
+
+        if m.len() != 1 || !m.contains_key(&(to.0 + to.1 * 8)) {
+            panic!();
+        }
+
+        println!("Move success: {}", g.make_move(from, to));
+        let a = g.get_piece_at(to.0, to.1);
+
+        println!("Debug Piece: {:#?}", a);
+        g.print_board();
+
+        if let Some(p) = a {
+            if p.color != Color::White || p.last_moved != Some(1) || p.times_moved != 1 {
+                panic!();
+            }
+        } else {
+            panic!();
+        }
+
+        // Also make sure it can not move in such a way twice
+        println!("Move success (should be false): {}", g.make_move(from, to));
+
+        let b = g.get_piece_at(to2.0, to2.1);
+
+        if let Some(_p) = b {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn test_pawn_move_capture_false() {
+        let g = Game::new();
+        let p = g.get_piece_at(4, 1).unwrap();
+        let m = p.moves[2].prune(&g, (4, 1));
+
+        if !m.is_empty() {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn test_pawn_move_capture_true() {
+        let mut template = TEST_TEMPLATE;
+        let start = (3_u8, 3_u8);
+        let goal = (4_u8, 4_u8);
+
+        template[(start.0 + start.1 * 8) as usize] = 'p';
+        template[(goal.0 + goal.1 * 8) as usize] = 'p';
+
+        let b = Game::make_board(template, COLOR_TEMPLATE).ok().unwrap();
+
+        let mut g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        g.print_moves(start.0, start.1);
+
+        let p = g.get_piece_at(start.0, start.1).unwrap();
+        let m = p.moves[2].prune(&g, start);
+
+        if m.len() != 1 || !m.contains_key(&(goal.0 + goal.1 * 8)) {
+            panic!();
+        }
+
+        println!("Move success: {}", g.make_move(start, goal));
+        let a = g.get_piece_at(goal.0, goal.1);
+
+        println!("Debug Piece: {:#?}", a);
+        g.print_board();
+
+        if let Some(p) = a {
+            if p.color == Color::White && p.last_moved == Some(1) && p.times_moved == 1 {
+                return;
+            }
+        }
+        panic!();
+    }
+
+    #[test]
+    fn test_pawn_move_en_passant() {
+        let mut template = TEST_TEMPLATE;
+        let start = (3_u8, 3_u8);
+        let subgoal = (3_u8, 4_u8);
+        let goal = (4_u8, 5_u8);
+        let start2 = (4_u8, 6_u8);
+        let goal2 = (4_u8, 4_u8);
+
+        template[(start.0 + start.1 * 8) as usize] = 'p';
+        template[(start2.0 + start2.1 * 8) as usize] = 'p';
+
+        let b = Game::make_board(template, COLOR_TEMPLATE).ok().unwrap();
+
+        let mut g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        println!("Move part 1 success: {}", g.make_move(start, subgoal));
+
+        println!("Move part 2 success: {}", g.make_move(start2, goal2));
+
+        println!("Move part 3 success: {}", g.make_move(subgoal, goal));
+
+        let a = g.get_piece_at(goal.0, goal.1);
+        let b = g.get_piece_at(goal2.0, goal2.1);
+
+        println!("Debug Piece: {:#?}", a);
+        g.print_board();
+
+        if let Some(p) = a {
+            if let Some(_p) = b {
+                panic!()
+            }
+            if p.color == Color::White && p.last_moved == Some(2) && p.times_moved == 2 {
+                return;
+            }
+        }
+        panic!();
+    }
+
+    #[test]
+    fn test_pawn_move_capture_true_flip() {
+        let mut template = TEST_TEMPLATE;
+        let start = (4_u8, 3_u8);
+        let goal = (3_u8, 4_u8);
+
+        template[(start.0 + start.1 * 8) as usize] = 'p';
+        template[(goal.0 + goal.1 * 8) as usize] = 'p';
+
+        let b = Game::make_board(template, COLOR_TEMPLATE).ok().unwrap();
+
+        let mut g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        g.print_moves(start.0, start.1);
+
+        let p = g.get_piece_at(start.0, start.1).unwrap();
+        let m = p.moves[2].prune(&g, start);
+
+        if m.len() != 1 || !m.contains_key(&(goal.0 + goal.1 * 8)) {
+            panic!();
+        }
+
+        println!("Move success: {}", g.make_move(start, goal));
+        let a = g.get_piece_at(goal.0, goal.1);
+
+        println!("Debug Piece: {:#?}", a);
+        g.print_board();
+
+        if let Some(p) = a {
+            if p.color == Color::White && p.last_moved == Some(1) && p.times_moved == 1 {
+                return;
+            }
+        }
+        panic!();
+    }
+
+    #[test]
+    fn test_pawn_black_move_normal() {
+        let g = Game::new();
+        let p = g.get_piece_at(4, 6).unwrap();
+        let m = p.moves[0].prune(&g, (4, 6));
+
+        g.print_moves(4, 6);
+
+        if m.len() != 1 || !m.contains_key(&(4 + 5 * 8)) {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn test_pawn_black_move_double() {
+        let g = Game::new();
+        let p = g.get_piece_at(4, 6).unwrap();
+        let m = p.moves[1].prune(&g, (4, 6));
+
+        g.print_moves(4, 6);
+
+        if m.len() != 1 || !m.contains_key(&(4 + 4 * 8)) {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn test_pawn_black_move_capture_false() {
+        let g = Game::new();
+        let p = g.get_piece_at(4, 6).unwrap();
+        let m = p.moves[2].prune(&g, (4, 6));
+
+        g.print_moves(4, 6);
+
+        if !m.is_empty() {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn test_pawn_black_move_capture_true() {
+        let mut template = TEST_TEMPLATE;
+        template[3 + 3 * 8] = 'p';
+        template[4 + 4 * 8] = 'p';
+        let b = Game::make_board(template, COLOR_TEMPLATE).ok().unwrap();
+        let g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 0,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        g.print_moves(4, 4);
+
+        let p = g.get_piece_at(4, 4).unwrap();
+        let m = p.moves[2].prune(&g, (4, 4));
+
+        if m.len() != 1 || !m.contains_key(&(3 + 3 * 8)) {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn test_pawn_black_move_capture_true_flip() {
+        let mut template = TEST_TEMPLATE;
+        template[4 + 3 * 8] = 'p';
+        template[3 + 4 * 8] = 'p';
+        let b = Game::make_board(template, COLOR_TEMPLATE).ok().unwrap();
+        let g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 0,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        g.print_moves(3, 4);
+
+        let p = g.get_piece_at(3, 4).unwrap();
+        let m = p.moves[2].prune(&g, (3, 4));
+
+        if m.len() != 1 || !m.contains_key(&(4 + 3 * 8)) {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn test_promote() {
+        let mut g = Game::new();
+        g.just_move((2, 1), (2, 7));
+        g.just_move((7, 6), (7, 7));
+
+        g.print_board();
+
+        print!("This move should fail: ");
+        if g.make_move((1, 1), (1, 2)) {
+            panic!("The move did not fail.");
+        }
+
+        print!("This move should fail: ");
+        if g.make_move((1, 6), (1, 5)) {
+            panic!("The move did not fail.");
+        }
+
+        if let Some((pos, piece)) = g.get_promotion() {
+            if piece.rank != 'p' || piece.color != Color::White || pos != (2, 7) {
+                panic!("Wrong piece or position.");
+            }
+
+            if g.promote(pos, 'p')
+                || g.promote(pos, 'K')
+                || g.promote((4, 6), 'Q')
+                || g.promote((7, 7), 'Q')
+            {
+                g.print_board();
+                panic!("It should not have promoted!");
+            }
+
+            if !g.promote(pos, 'Q') {
+                panic!("It should have promoted, but did not.");
+            }
+
+            if let Some(p) = g.get_piece_at(pos.0, pos.1) {
+                if p.rank != 'Q' {
+                    panic!("It lied about promoting!");
+                }
+                println!("Success!");
+            } else {
+                panic!("It is just gone. What?");
+            }
+        } else {
+            panic!("No piece to promote.");
+        }
+    }
+
+    #[test]
+    fn test_pawn_black_move_en_passant() {
+        let mut template = TEST_TEMPLATE;
+        template[4 + 8] = 'p';
+        template[5 + 3 * 8] = 'p';
+        // The black pawn is in the white half of the color template
+        let colors = COLOR_TEMPLATE & !(1 << (5 + 3 * 8));
+
+        let b = Game::make_board(template, colors).unwrap();
+        let mut g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        if !g.make_move((4, 1), (4, 3)) {
+            panic!("The double step failed.");
+        }
+
+        // Black captures to the left, so the effect has to be mirrored.
+        if !g.make_move((5, 3), (4, 2)) {
+            g.print_moves(5, 3);
+            panic!("En passant failed.");
+        }
+        g.print_board();
+
+        if g.get_piece_at(4, 3).is_some() {
+            panic!("The white pawn was not captured.");
+        }
+        if g.get_piece_at(4, 2).unwrap().color != Color::Black {
+            panic!("The black pawn did not move.");
+        }
+    }
+
+    #[test]
+    fn test_pawn_facing_right() {
+        let mut template = TEST_TEMPLATE;
+        template[2 + 4 * 8] = 'p';
+        let mut b = Game::make_board(template, COLOR_TEMPLATE).unwrap();
+        b[1 + 3 * 8] = Some(Piece::new_pawn_facing(Color::White, (1, 0)));
+
+        let mut g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+        g.print_moves(1, 3);
+
+        let m = g.get_moves(1, 3).unwrap();
+        if m.len() != 3
+            || !m.contains_key(&(2 + 3 * 8))
+            || !m.contains_key(&(3 + 3 * 8))
+            || !m.contains_key(&(2 + 4 * 8))
+        {
+            panic!("Wrong moves: {:?}", m.keys());
+        }
+
+        if !g.make_move((1, 3), (2, 4)) {
+            panic!("The capture failed.");
+        }
+
+        // It promotes at the right edge, not the top.
+        let p = g.get_piece_at(2, 4).unwrap();
+        if !p.promotes_at(7, 4) || p.promotes_at(2, 7) {
+            panic!("It promotes at the wrong place.");
+        }
+    }
+
+    #[test]
+    fn test_is_check() {
+        let mut t = TEST_TEMPLATE;
+        t[1 + 5 * 8] = 'R';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        if !g.is_check() {
+            panic!("The king is attacked by the rook.");
+        }
+
+        if Game::new().is_check() {
+            panic!("Nobody is in check at the start.");
+        }
+    }
+
+    #[test]
+    fn test_best_move_with() {
+        let mut t = TEST_TEMPLATE;
+        t[3 + 3 * 8] = 'R';
+        t[3 + 5 * 8] = 'Q';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        let default = g.best_move(2);
+        let custom = g.best_move_with(2, &|g: &Game| g.material_balance());
+
+        if default != Some(((3, 3), (3, 5))) {
+            panic!("It should take the queen, but did {:?}", default);
+        }
+        if default != custom {
+            panic!("The material closure picked {:?} instead", custom);
+        }
+    }
+
+    #[test]
+    fn test_ordered_moves() {
+        let mut t = TEST_TEMPLATE;
+        t[3 + 3 * 8] = 'p';
+        t[4 + 4 * 8] = 'Q';
+        t[3 * 8] = 'Q';
+        t[5 * 8] = 'p';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        let m = g.ordered_moves();
+        let pawn_takes_queen = m.iter().position(|x| *x == ((3, 3), (4, 4))).unwrap();
+        let queen_takes_pawn = m.iter().position(|x| *x == ((0, 3), (0, 5))).unwrap();
+
+        if pawn_takes_queen != 0 || queen_takes_pawn != 1 {
+            panic!("Wrong order: {:?}", m);
+        }
+    }
+
+    #[test]
+    fn test_display_position_and_effect() {
+        let e4 = Position::Global((4, 3));
+        let a1 = Position::Global((0, 0));
+        let d1 = Position::Global((3, 0));
+
+        assert_eq!(e4.to_string(), "e4");
+        assert_eq!(Position::Relative((1, 0)).to_string(), "(+1,+0)");
+        assert_eq!(Position::Relative((-2, 1)).to_string(), "(-2,+1)");
+        assert_eq!(Effect::Capture(e4).to_string(), "x e4");
+        assert_eq!(Effect::Move(a1, d1).to_string(), "a1→d1");
+    }
+
+    #[test]
+    fn test_capture_target() {
+        let mut template = TEST_TEMPLATE;
+        template[3 + 3 * 8] = 'p';
+        template[4 + 6 * 8] = 'p';
+        template[2 + 5 * 8] = 'N';
+
+        let b = Game::make_board(template, COLOR_TEMPLATE).unwrap();
+        let mut g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        g.make_move((3, 3), (3, 4));
+        g.make_move((4, 6), (4, 4));
+
+        // En passant lands on (4,5) but takes the pawn behind it.
+        if g.capture_target((3, 4), (4, 5)) != Some((4, 4, 'p')) {
+            panic!(
+                "Wrong en passant target: {:?}",
+                g.capture_target((3, 4), (4, 5))
+            );
+        }
+
+        if g.capture_target((3, 4), (2, 5)) != Some((2, 5, 'N')) {
+            panic!("Wrong target: {:?}", g.capture_target((3, 4), (2, 5)));
+        }
+
+        if g.capture_target((3, 4), (3, 5)).is_some() {
+            panic!("A quiet move should not capture anything.");
+        }
+    }
+
+    #[test]
+    fn test_see() {
+        let mut t = TEST_TEMPLATE;
+        t[4 + 8] = 'Q';
+        t[4 + 4 * 8] = 'p';
+        t[5 + 5 * 8] = 'p';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let mut g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        if g.attackers_of(4, 4, Color::White) != vec![(4, 1)] {
+            panic!("Wrong attackers: {:?}", g.attackers_of(4, 4, Color::White));
+        }
+
+        // The pawn is defended, so the queen is lost for a pawn.
+        if g.see((4, 1), (4, 4)) != 100 - 900 {
+            panic!("Wrong exchange: {}", g.see((4, 1), (4, 4)));
+        }
+
+        // Without the defender it just wins a pawn.
+        g.put(5, 5, None);
+        if g.see((4, 1), (4, 4)) != 100 {
+            panic!("Wrong exchange: {}", g.see((4, 1), (4, 4)));
+        }
+    }
+
+    #[test]
+    fn test_see_king_recapture() {
+        // The king defends d5, but it can not take back, since the bishop covers d5 too.
+        let g = Game::from_placements(
+            &[
+                (0, 0, 'K', Color::White),
+                (3, 0, 'R', Color::White),
+                (1, 2, 'B', Color::White),
+                (3, 5, 'K', Color::Black),
+                (3, 4, 'p', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        if g.see((3, 0), (3, 4)) != 100 {
+            panic!("The rook just wins the pawn, got {}", g.see((3, 0), (3, 4)));
+        }
+
+        // Without the bishop, the king takes the rook.
+        let mut g = g;
+        g.put(1, 2, None);
+        if g.see((3, 0), (3, 4)) != 100 - 500 {
+            panic!(
+                "The king should take the rook, got {}",
+                g.see((3, 0), (3, 4))
+            );
+        }
+    }
+
+    #[test]
+    fn test_chess_move() {
+        let mut g = Game::new();
+
+        let e2e4 = ChessMove {
+            from: (4, 1),
+            to: (4, 3),
+            promotion: None,
+        };
+        if g.make(e2e4) != Ok(()) || g.get_piece_at(4, 3).is_none() {
+            panic!("e2e4 should work.");
+        }
+        if g.make(e2e4) != Err(MoveError::NoPiece) {
+            panic!("There is nothing on e2 anymore.");
+        }
+
+        let wrong = ChessMove {
+            from: (3, 1),
+            to: (3, 2),
+            promotion: None,
+        };
+        if g.make(wrong) != Err(MoveError::WrongColor) {
+            panic!("It is black's turn.");
+        }
+
+        let mut t = TEST_TEMPLATE;
+        t[3 + 6 * 8] = 'p';
+        let b = Game::make_board(t, COLOR_TEMPLATE | 1 << (3 + 6 * 8)).unwrap();
+        let mut g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        let mut promote = ChessMove {
+            from: (3, 6),
+            to: (3, 7),
+            promotion: None,
+        };
+        if g.make(promote) != Err(MoveError::MissingPromotion) {
+            panic!("It should need a promotion.");
+        }
+        promote.promotion = Some('K');
+        if g.make(promote) != Err(MoveError::InvalidPromotion) {
+            panic!("It should not promote to a king.");
+        }
+
+        if g.legal_chess_moves()
+            .iter()
+            .filter(|m| m.from == (3, 6))
+            .count()
+            != 4
+        {
+            panic!("There should be one move per promotion rank.");
+        }
+
+        promote.promotion = Some('N');
+        if g.make(promote) != Ok(()) {
+            panic!("It should have promoted.");
+        }
+        if g.get_piece_at(3, 7).unwrap().rank != 'N' || g.get_turn_owner() != Color::Black {
+            panic!("The promotion did not finish.");
+        }
+    }
+
+    #[test]
+    fn test_forced_move() {
+        let mut t = ['0'; 64];
+        t[0] = 'K';
+        t[2 + 2 * 8] = 'K';
+        t[7 + 8] = 'R';
+        let b = Game::make_board(t, 1).unwrap();
+        let g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        if g.forced_move() != Some(((0, 0), (1, 0))) {
+            g.print_moves(0, 0);
+            panic!("The king only has one square to go to.");
+        }
+
+        if Game::new().forced_move().is_some() {
+            panic!("There are plenty of moves at the start.");
+        }
+    }
+
+    #[test]
+    fn test_diff_castling() {
+        let mut t = [
+            'R', 'N', 'B', 'Q', 'K', '0', '0', 'R', //
+            'p', 'p', 'p', 'p', 'p', 'p', 'p', 'p', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            'p', 'p', 'p', 'p', 'p', 'p', 'p', 'p', //
+            'R', 'N', 'B', 'Q', 'K', 'B', 'N', 'R', //
+        ];
+        let b = Game::make_board(t, 0xFFFF).unwrap();
+        let before = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        let mut after = before.clone();
+        if !after.make_move((4, 0), (6, 0)) {
+            after.print_moves(4, 0);
+            panic!("It should be able to castle.");
+        }
+
+        let d = before.diff(&after);
+        if d != vec![
+            (4, 0, Some('K'), None),
+            (5, 0, None, Some('R')),
+            (6, 0, None, Some('K')),
+            (7, 0, Some('R'), None),
+        ] {
+            panic!("Wrong diff: {:?}", d);
+        }
+
+        // Black can castle too, but not through check.
+        t[5 + 7 * 8] = '0';
+        t[6 + 7 * 8] = '0';
+        t[5 + 6 * 8] = '0';
+        t[5 + 4 * 8] = 'R';
+        let b = Game::make_board(t, 0xFFFF | 1 << (5 + 4 * 8)).unwrap();
+        let mut g = Game {
+            board: b,
+            turn_owner: Color::Black,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+        if g.make_move((4, 7), (6, 7)) {
+            panic!("It castled through check.");
+        }
+        g.put(5, 4, None);
+        if !g.make_move((4, 7), (6, 7)) {
+            panic!("Black should be able to castle.");
+        }
+    }
+
+    #[test]
+    fn test_move_events() {
+        let mut t = TEST_TEMPLATE;
+        t[3 + 6 * 8] = 'p';
+        t[4 + 7 * 8] = 'R';
+        let b = Game::make_board(t, COLOR_TEMPLATE | 1 << (3 + 6 * 8)).unwrap();
+        let mut g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        if g.make_move_events((3, 6), (3, 5)) != Err(MoveError::IllegalMove) {
+            panic!("Pawns do not walk backwards.");
+        }
+
+        let events = g.make_move_events((3, 6), (4, 7)).unwrap();
+        if events
+            != vec![
+                GameEvent::PieceCaptured {
+                    at: (4, 7),
+                    rank: 'R',
+                },
+                GameEvent::PieceMoved {
+                    from: (3, 6),
+                    to: (4, 7),
+                },
+                GameEvent::StateChanged {
+                    to: GameState::Promote,
+                },
+            ]
+        {
+            panic!("Wrong move events: {:?}", events);
+        }
+
+        // The new queen puts the black king in check right away.
+        let events = g.promote_events((4, 7), 'Q').unwrap();
+        if events
+            != vec![
+                GameEvent::PiecePromoted {
+                    at: (4, 7),
+                    from_rank: 'p',
+                    to_rank: 'Q',
+                },
+                GameEvent::TurnChanged { to: Color::Black },
+                GameEvent::StateChanged {
+                    to: GameState::Check,
+                },
+            ]
+        {
+            panic!("Wrong promotion events: {:?}", events);
+        }
+    }
+
+    #[test]
+    fn test_render_coordinates() {
+        let g = Game::new();
+        let plain = g.render(&RenderOptions::default());
+        let labeled = g.render(&RenderOptions {
+            show_coordinates: true,
+            ..Default::default()
+        });
+
+        if plain.contains(" a  b  c ") {
+            panic!("There should be no coordinates by default.");
+        }
+        if !labeled.contains(" a  b  c  d  e  f  g  h ") || !labeled.starts_with("8 ") {
+            panic!("The coordinates are missing.");
+        }
+
+        // Seen from black, the files go the other way.
+        let flipped = g.render(&RenderOptions {
+            show_coordinates: true,
+            perspective: Color::Black,
+            unicode: true,
+            ..Default::default()
+        });
+        if !flipped.contains(" h  g  f  e  d  c  b  a ") || !flipped.starts_with("1 ") {
+            panic!("The board is not flipped.");
+        }
+        if !flipped.contains('♔') || !flipped.contains('♟') {
+            panic!("There are no unicode pieces.");
+        }
+
+        // Showing the moves of a piece draws the pieces the same way as the plain board.
+        let moves = g.render(&RenderOptions {
+            moves_of: Some((1, 0)),
+            ..Default::default()
+        });
+        if !moves.contains("<K>") || moves.contains("[K]") {
+            panic!("Black pieces should look the same in both.");
+        }
+    }
+
+    #[test]
+    fn test_print_moves_format() {
+        let g = Game::new();
+        let out = g.render(&RenderOptions {
+            light_square: String::new(),
+            moves_of: Some((1, 0)),
+            ..Default::default()
+        });
+        // Take out the colors, so only the pieces are left.
+        let mut plain = String::new();
+        let mut in_code = false;
+        for ch in out.chars() {
+            match ch {
+                '\x1b' => in_code = true,
+                'm' if in_code => in_code = false,
+                _ if !in_code => plain.push(ch),
+                _ => {}
+            }
+        }
+        let lines: Vec<&str> = plain.lines().collect();
+        if lines[0] != "<R><N><B><Q><K><B><N><R>" || lines[7] != "(R)(N)(B)(Q)(K)(B)(N)(R)" {
+            panic!("Black should be <K> and white (K), got {:?}", lines);
+        }
+        if lines[1] != "<p>".repeat(8) {
+            panic!("Black pawns should be <p>, got {:?}", lines[1]);
+        }
+    }
+
+    #[test]
+    fn test_mirror_vertical() {
+        let mut g = Game::new();
+        g.make_move((4, 1), (4, 3));
+        g.make_move((3, 6), (3, 4));
+        g.make_move((4, 3), (4, 4));
+        g.make_move((5, 6), (5, 4));
+
+        let m = g.mirror_vertical();
+
+        if m.get_turn_owner() != Color::Black || m.get_piece_at(4, 3).unwrap().color != Color::Black
+        {
+            panic!("The colors were not swapped.");
+        }
+
+        let mut expected: Vec<_> = g
+            .legal_moves()
+            .into_iter()
+            .map(|(f, t)| ((f.0, 7 - f.1), (t.0, 7 - t.1)))
+            .collect();
+        let mut mirrored = m.legal_moves();
+        expected.sort();
+        mirrored.sort();
+
+        // En passant on f6 should show up as en passant on f3.
+        if !mirrored.contains(&((4, 3), (5, 2))) {
+            panic!("En passant did not survive the mirror.");
+        }
+        if expected.len() != mirrored.len() || expected != mirrored {
+            panic!(
+                "The moves are not mirrored: {:?} vs {:?}",
+                expected, mirrored
+            );
+        }
+    }
+
+    #[test]
+    fn test_en_passant_needs_double_step() {
+        let mut t = TEST_TEMPLATE;
+        t[5 + 2 * 8] = 'p';
+        t[4 + 3 * 8] = 'N';
+        t[3 + 3 * 8] = 'p';
+        let colors = COLOR_TEMPLATE & !(1 << (4 + 3 * 8)) & !(1 << (3 + 3 * 8));
+        let b = Game::make_board(t, colors).unwrap();
+        let mut g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        // The pawn gets to the fourth row with its first move, but not with a double step.
+        if !g.make_move((5, 2), (4, 3)) {
+            panic!("The capture failed.");
+        }
+        g.print_moves(3, 3);
+
+        if g.get_moves(3, 3).unwrap().contains_key(&(4 + 2 * 8)) {
+            panic!("En passant should not be possible.");
+        }
+    }
+
+    #[test]
+    fn test_is_checkmate_and_stalemate() {
+        let mut t = ['0'; 64];
+        t[63] = 'K';
+        t[6 + 6 * 8] = 'Q';
+        t[5 + 5 * 8] = 'K';
+        let white = 1 << (6 + 6 * 8) | 1 << (5 + 5 * 8);
+        let b = Game::make_board(t, white).unwrap();
+        let mate = Game {
+            board: b,
+            turn_owner: Color::Black,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        if !mate.is_checkmate() || mate.is_stalemate() {
+            panic!("It should be check mate.");
+        }
+
+        let mut t = ['0'; 64];
+        t[63] = 'K';
+        t[5 + 6 * 8] = 'Q';
+        t[0] = 'K';
+        let white = 1 << (5 + 6 * 8) | 1;
+        let b = Game::make_board(t, white).unwrap();
+        let stale = Game {
+            board: b,
+            turn_owner: Color::Black,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        if !stale.is_stalemate() || stale.is_checkmate() {
+            panic!("It should be stalemate.");
+        }
+
+        let g = Game::new();
+        if g.is_checkmate() || g.is_stalemate() {
+            panic!("The game just started.");
+        }
+    }
+
+    #[test]
+    fn test_game_state_variants() {
+        // No wildcard here, so this stops compiling if a variant is added back.
+        for state in [
+            GameState::Running,
+            GameState::Promote,
+            GameState::Check,
+            GameState::CheckMate,
+            GameState::Stalemate,
+            GameState::CrucialCaptured,
+            GameState::StalemateWin,
+        ] {
+            match state {
+                GameState::Running
+                | GameState::Promote
+                | GameState::Check
+                | GameState::CheckMate
+                | GameState::Stalemate
+                | GameState::CrucialCaptured
+                | GameState::StalemateWin => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_broken_move_is_refused() {
+        let mut g = Game::from_placements(
+            &[(4, 0, 'K', Color::White), (4, 7, 'K', Color::Black)],
+            Color::White,
+        )
+        .unwrap();
+        // A rook that also drags along a piece from the square next to it, which is empty.
+        let mut rook = Piece::new(Color::White, 'R');
+        for m in &mut rook.moves {
+            m.effect = vec![Effect::Move(
+                Position::Relative((1, 0)),
+                Position::Relative((1, 1)),
+            )];
+        }
+        g.put(0, 3, Some(rook));
+        let before = g.clone();
+
+        let mv = ChessMove {
+            from: (0, 3),
+            to: (0, 4),
+            promotion: None,
+        };
+        if g.make(mv) != Err(MoveError::BrokenMove) {
+            panic!("The broken move should be refused.");
+        }
+        if g.make_move((0, 3), (0, 4)) || g.diff(&before) != Vec::new() {
+            panic!("The broken move should not change anything.");
+        }
+    }
+
+    #[test]
+    fn test_promotion_rule_corners() {
+        let mut t = TEST_TEMPLATE;
+        t[6 * 8] = 'p';
+        t[3 + 6 * 8] = 'p';
+        let b = Game::make_board(t, COLOR_TEMPLATE | 1 << (6 * 8) | 1 << (3 + 6 * 8)).unwrap();
+        let mut g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        let rule = PromotionRule {
+            squares: Some(vec![(0, 0), (0, 7), (7, 0), (7, 7)]),
+            ranks: vec!['Q'],
+            color: None,
+        };
+        for col in [0, 3] {
+            g.put(
+                col,
+                6,
+                Some(Piece {
+                    promotion: Some(rule.clone()),
+                    ..Piece::new_pawn(Color::White)
+                }),
+            );
+        }
+
+        // Not a corner, so nothing happens.
+        g.make_move((3, 6), (3, 7));
+        if g.get_game_state() == GameState::Promote {
+            panic!("It should only promote in the corners.");
+        }
+        g.make_move((6, 7), (6, 6));
+
+        g.make_move((0, 6), (0, 7));
+        if g.get_promotion().map(|(pos, _)| pos) != Some((0, 7)) {
+            panic!("It should promote in the corner.");
+        }
+        if g.promote((0, 7), 'R') {
+            panic!("Rooks are not allowed by the rule.");
+        }
+        if !g.promote((0, 7), 'Q') {
+            panic!("It should become a queen.");
+        }
+    }
+
+    #[test]
+    fn test_xray_attacks() {
+        let mut t = TEST_TEMPLATE;
+        t[3 * 8] = 'R';
+        t[3 + 3 * 8] = 'p';
+        t[6 + 3 * 8] = 'Q';
+        t[7 + 3 * 8] = 'p';
+        let b = Game::make_board(
+            t,
+            COLOR_TEMPLATE & !(1 << (6 + 3 * 8)) & !(1 << (7 + 3 * 8)),
+        )
+        .unwrap();
+        let g = Game {
+            board: b,
+            turn_owner: Color::White,
+            turn_count: 1,
+            game_state: GameState::Running,
+            ..Default::default()
+        };
+
+        let xray = g.xray_attacks(0, 3);
+        // Through its own pawn and onto the queen, but not past her.
+        if !xray.contains(&(3 + 3 * 8))
+            || !xray.contains(&(6 + 3 * 8))
+            || xray.contains(&(7 + 3 * 8))
+        {
+            panic!("Wrong x-ray: {:?}", xray);
+        }
+
+        let normal = g.get_piece_at(0, 3).unwrap().get_danger_zone(0, 3, &g);
+        if normal.contains(&(6 + 3 * 8)) {
+            panic!("The queen should only be hit through the pawn.");
+        }
+    }
+
+    #[test]
+    fn test_from_parts() {
+        let mut t = ['0'; 64];
+        t[63] = 'K';
+        t[6 + 6 * 8] = 'Q';
+        t[5 + 5 * 8] = 'K';
+        let b = Game::make_board(t, 1 << (6 + 6 * 8) | 1 << (5 + 5 * 8)).unwrap();
+
+        let g = Game::from_parts(b.clone(), Color::Black, 1);
+        if g.get_game_state() != GameState::CheckMate {
+            panic!("It should be check mate.");
+        }
+
+        let g = Game::from_parts(b, Color::White, 1);
+        if g.get_game_state() != GameState::Running {
+            panic!("White is fine.");
+        }
+    }
+
+    #[test]
+    fn test_legal_moves_from() {
+        let mut t = TEST_TEMPLATE;
+        t[3 + 6 * 8] = 'p';
+        let b = Game::make_board(t, COLOR_TEMPLATE | 1 << (3 + 6 * 8)).unwrap();
+        let g = Game::from_parts(b, Color::White, 1);
+
+        let moves = g.legal_moves_from(3, 6);
+        if moves.len() != 4 {
+            panic!("Expected 4 promotions, got {:?}", moves);
+        }
+        for rank in PROMOTION_RANKS {
+            let m = ChessMove {
+                from: (3, 6),
+                to: (3, 7),
+                promotion: Some(rank),
+            };
+            if !moves.contains(&m) {
+                panic!("Missing the promotion to {}", rank);
+            }
+        }
+
+        // Not White's piece
+        if !g.legal_moves_from(6, 7).is_empty() {
+            panic!("Black should not be able to move on White's turn.");
+        }
+        if !g.legal_moves_from(4, 4).is_empty() {
+            panic!("There is nothing there.");
+        }
+    }
+
+    #[test]
+    fn test_perft_promotion() {
+        let mut t = TEST_TEMPLATE;
+        t[3 + 6 * 8] = 'p';
+        let b = Game::make_board(t, COLOR_TEMPLATE | 1 << (3 + 6 * 8)).unwrap();
+        let g = Game::from_parts(b, Color::White, 1);
+
+        // 5 king moves, and the pawn can become 4 different things.
+        if g.perft(1) != 9 {
+            panic!("Expected 9 moves, got {}", g.perft(1));
+        }
+
+        // Every promotion should lead to its own position.
+        let mut sum = 0;
+        for mv in g.legal_chess_moves() {
+            let mut g2 = g.clone();
+            if g2.make(mv).is_err() {
+                panic!("Could not make {:?}", mv);
+            }
+            sum += g2.perft(1);
+        }
+        if g.perft(2) != sum {
+            panic!("perft(2) should be {}, got {}", sum, g.perft(2));
+        }
+
+        if Game::new().perft(2) != 400 {
+            panic!("The starting position should have 400 positions after 2 moves.");
+        }
+    }
+
+    #[test]
+    fn test_attack_map_cache() {
+        // The cache has to be something that can be shared between threads.
+        fn shareable<T: Send + Sync>(_: &T) {}
+        shareable(&Game::new());
+
+        // What the attack maps would be if nothing had been cached yet.
+        let fresh = |g: &Game| {
+            let mut g = g.clone();
+            g.attacks = Default::default();
+            [g.attack_map(Color::White), g.attack_map(Color::Black)]
+        };
+        let cached = |g: &Game| [g.attack_map(Color::White), g.attack_map(Color::Black)];
+
+        let mut g = Game::new();
+        let moves = [
+            ((4, 1), (4, 3)),
+            ((3, 6), (3, 4)),
+            ((4, 3), (3, 4)),
+            ((3, 7), (3, 4)),
+            ((1, 0), (2, 2)),
+            ((3, 4), (0, 4)),
+        ];
+
+        for (from, to) in moves {
+            // Fill the cache before the move, so a stale one would show.
+            cached(&g);
+            let before = g.clone();
+            if !g.make_move(from, to) {
+                panic!("Could not move {:?} to {:?}", from, to);
+            }
+            if cached(&g) != fresh(&g) {
+                panic!("The attack map is stale after {:?}", to);
+            }
+
+            // Going back to the copy from before has to give the old maps back.
+            let after = std::mem::replace(&mut g, before);
+            if cached(&g) != fresh(&g) || cached(&g) == cached(&after) {
+                panic!("The attack map is wrong after taking back {:?}", to);
+            }
+            g = after;
+        }
+
+        // Editing the board by hand empties the cache too.
+        g.put(0, 4, None);
+        if cached(&g) != fresh(&g) {
+            panic!("The attack map is stale after removing the queen.");
+        }
+        g.set_turn(g.get_turn_owner().opposite());
+        if cached(&g) != fresh(&g) || g.is_check() != g.in_check() {
+            panic!("The attack map is stale after changing the turn.");
+        }
+    }
+
+    #[test]
+    fn test_rule_set() {
+        let t = [
+            'R', '0', '0', '0', 'K', '0', '0', 'R', //
+            'p', 'p', 'p', 'p', 'p', 'p', 'p', 'p', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            'p', 'p', 'p', 'p', 'p', 'p', 'p', 'p', //
+            'R', 'N', 'B', 'Q', 'K', 'B', 'N', 'R', //
+        ];
+        let b = Game::make_board(t, 0xFFFF).unwrap();
+        let mut g = Game::from_parts(b, Color::White, 1);
+
+        let king = g.get_moves(4, 0).unwrap();
+        if !king.contains_key(&6) || !king.contains_key(&2) {
+            panic!("It should be able to castle both ways.");
+        }
+
+        g.set_rules(RuleSet {
+            castling: false,
+            double_pawn_step: false,
+            ..Default::default()
+        });
+
+        let king = g.get_moves(4, 0).unwrap();
+        if king.contains_key(&6) || king.contains_key(&2) {
+            panic!("Castling is turned off.");
+        }
+        if g.get_moves(4, 1).unwrap().contains_key(&(4 + 3 * 8)) {
+            panic!("Double steps are turned off.");
+        }
+        if !g.get_moves(4, 1).unwrap().contains_key(&(4 + 2 * 8)) {
+            panic!("Single steps should still work.");
+        }
+    }
+
+    #[test]
+    fn test_square_color() {
+        if Game::square_color(0, 0) != Color::Black {
+            panic!("a1 is dark.");
+        }
+        if Game::square_color(7, 0) != Color::White {
+            panic!("h1 is light.");
+        }
+        // The queens stand on their own color.
+        if Game::square_color(3, 0) != Color::White || Game::square_color(3, 7) != Color::Black {
+            panic!("d1 is light and d8 is dark.");
+        }
+    }
+
+    #[test]
+    fn test_hanging_pieces() {
+        let mut t = TEST_TEMPLATE;
+        t[3 + 3 * 8] = 'N';
+        t[3 + 6 * 8] = 'R';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let g = Game::from_parts(b, Color::White, 1);
+
+        if g.hanging_pieces(Color::White) != vec![(3, 3)] {
+            panic!(
+                "The knight is hanging: {:?}",
+                g.hanging_pieces(Color::White)
+            );
+        }
+        if !g.hanging_pieces(Color::Black).is_empty() {
+            panic!("Nothing attacks the rook.");
+        }
+
+        // A pawn defends it, so the rook would lose more than it wins.
+        t[2 + 2 * 8] = 'p';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let g = Game::from_parts(b, Color::White, 1);
+        if !g.hanging_pieces(Color::White).is_empty() {
+            panic!("The knight is defended.");
+        }
+    }
+
+    #[test]
+    fn test_state_after_move() {
+        let mut g = Game::new();
+        g.make_move((4, 1), (4, 3));
+        if g.get_game_state() != GameState::Running {
+            panic!("Expected Running, got {:?}", g.get_game_state());
+        }
+
+        // A rook to the back row, with and without a way out.
+        let mut t = TEST_TEMPLATE;
+        t[3 + 3 * 8] = 'R';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let mut g = Game::from_parts(b, Color::White, 1);
+        g.make_move((3, 3), (3, 7));
+        if g.get_game_state() != GameState::Check {
+            panic!("Expected Check, got {:?}", g.get_game_state());
+        }
+
+        t[5 + 6 * 8] = 'p';
+        t[6 + 6 * 8] = 'p';
+        t[7 + 6 * 8] = 'p';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let mut g = Game::from_parts(b, Color::White, 1);
+        g.make_move((3, 3), (3, 7));
+        if g.get_game_state() != GameState::CheckMate {
+            panic!("Expected CheckMate, got {:?}", g.get_game_state());
+        }
+
+        // The queen takes every square from the king without checking it.
+        let mut t = ['0'; 64];
+        t[1] = 'K';
+        t[63] = 'K';
+        t[6 + 3 * 8] = 'Q';
+        let b = Game::make_board(t, 1 << 1 | 1 << (6 + 3 * 8)).unwrap();
+        let mut g = Game::from_parts(b, Color::White, 1);
+        g.make_move((6, 3), (6, 5));
+        if g.get_game_state() != GameState::Stalemate {
+            panic!("Expected Stalemate, got {:?}", g.get_game_state());
+        }
+    }
+
+    #[test]
+    fn test_to_char_array() {
+        let expected = [
+            'R', 'N', 'B', 'Q', 'K', 'B', 'N', 'R', //
+            'P', 'P', 'P', 'P', 'P', 'P', 'P', 'P', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            'p', 'p', 'p', 'p', 'p', 'p', 'p', 'p', //
+            'r', 'n', 'b', 'q', 'k', 'b', 'n', 'r', //
+        ];
+        if Game::new().to_char_array() != expected {
+            panic!("Wrong layout: {:?}", Game::new().to_char_array());
+        }
+    }
+
+    #[test]
+    fn test_is_threefold_now() {
+        let mut g = Game::new();
+        let dance = [
+            ((6, 0), (5, 2)),
+            ((6, 7), (5, 5)),
+            ((5, 2), (6, 0)),
+            ((5, 5), (6, 7)),
+        ];
+
+        for _ in 0..2 {
+            for (from, to) in dance {
+                if g.is_threefold_now() {
+                    panic!("It has not repeated three times yet.");
+                }
+                if !g.make_move(from, to) {
+                    panic!("Could not move {:?} to {:?}", from, to);
+                }
+            }
+        }
+
+        // Back at the start for the third time.
+        if !g.is_threefold_now() {
+            panic!("The starting position has been seen three times.");
+        }
+
+        g.make_move((4, 1), (4, 3));
+        if g.is_threefold_now() {
+            panic!("This position is new.");
+        }
+    }
+
+    #[test]
+    fn test_capture_the_king() {
+        let mut t = TEST_TEMPLATE;
+        t[6 + 3 * 8] = 'R';
+        t[3 + 6 * 8] = 'R';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let mut g = Game::from_parts(b, Color::Black, 1);
+        g.set_rules(RuleSet {
+            capture_the_king: true,
+            ..Default::default()
+        });
+
+        if g.get_game_state() != GameState::Running {
+            panic!("Check does not exist here, got {:?}", g.get_game_state());
+        }
+
+        // Black ignores the rook, which would not be allowed normally.
+        if !g.make_move((3, 6), (3, 5)) {
+            panic!("Black should be allowed to leave the king hanging.");
+        }
+
+        if !g.make_move((6, 3), (6, 7)) {
+            panic!("White should be able to take the king.");
+        }
+        if g.get_game_state() != GameState::CrucialCaptured {
+            panic!("The game should be over, got {:?}", g.get_game_state());
+        }
+        // The loser owns the turn, just like with check mate.
+        if g.get_turn_owner() != Color::Black {
+            panic!("Black lost.");
+        }
+        if g.make_move((3, 5), (3, 4)) {
+            panic!("The game is over.");
+        }
+    }
+
+    #[test]
+    fn test_material_signature() {
+        let mut t = TEST_TEMPLATE;
+        t[3 + 3 * 8] = 'R';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let g = Game::from_parts(b, Color::White, 1);
+        if g.material_signature() != "KRvK" {
+            panic!("Expected KRvK, got {}", g.material_signature());
+        }
+
+        if Game::new().material_signature() != "KQRRBBNNPPPPPPPPvKQRRBBNNPPPPPPPP" {
+            panic!("Wrong start: {}", Game::new().material_signature());
+        }
+    }
+
+    #[test]
+    fn test_can_castle() {
+        let g = Game::new();
+        if g.can_castle(Color::White) != (false, false)
+            || g.can_castle(Color::Black) != (false, false)
+        {
+            panic!("Everything is in the way.");
+        }
+
+        let t = [
+            'R', 'N', 'B', 'Q', 'K', '0', '0', 'R', //
+            'p', 'p', 'p', 'p', 'p', 'p', 'p', 'p', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            'p', 'p', 'p', 'p', 'p', 'p', 'p', 'p', //
+            'R', 'N', 'B', 'Q', 'K', 'B', 'N', 'R', //
+        ];
+        let b = Game::make_board(t, 0xFFFF).unwrap();
+        let g = Game::from_parts(b, Color::White, 1);
+        if g.can_castle(Color::White) != (true, false) {
+            panic!(
+                "White can castle king side: {:?}",
+                g.can_castle(Color::White)
+            );
+        }
+    }
+
+    #[test]
+    fn test_can_castle_in_check() {
+        let t = [
+            'R', '0', '0', '0', 'K', '0', '0', 'R', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', 'R', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            '0', '0', '0', '0', '0', '0', '0', '0', //
+            'R', '0', '0', '0', 'K', '0', '0', 'R', //
+        ];
+        // The white rook on e5 checks the black king.
+        let b = Game::make_board(t, 0xFF | 1 << (4 + 4 * 8)).unwrap();
+        let g = Game::from_parts(b, Color::White, 1);
+        if g.can_castle(Color::Black) != (false, false) {
+            panic!("Black is in check: {:?}", g.can_castle(Color::Black));
+        }
+        if g.can_castle(Color::White) != (true, true) {
+            panic!("White is fine: {:?}", g.can_castle(Color::White));
+        }
+
+        // Moved over to f5, it only covers f8, so only the queen side is left for black.
+        let mut g = g;
+        g.put(4, 4, None);
+        g.put(5, 4, Some(Piece::new(Color::White, 'R')));
+        if g.can_castle(Color::Black) != (false, true) {
+            panic!(
+                "Black can not castle through f8: {:?}",
+                g.can_castle(Color::Black)
+            );
+        }
+    }
+
+    #[test]
+    fn test_checking_moves() {
+        let mut t = TEST_TEMPLATE;
+        t[3 * 8] = 'R';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let g = Game::from_parts(b, Color::White, 1);
+
+        let checks = g.checking_moves();
+        if checks != vec![((0, 3), (6, 3)), ((0, 3), (0, 7))] {
+            panic!("Expected the two rook checks, got {:?}", checks);
+        }
+        if g.gives_check((1, 0), (1, 1)) {
+            panic!("The king can not give check.");
+        }
+    }
+
+    #[test]
+    fn test_editor() {
+        let g = Game::editor()
+            .place(4, 0, 'K', Color::White)
+            .place(3, 0, 'Q', Color::White)
+            .place(4, 7, 'K', Color::Black)
+            .place(0, 7, 'R', Color::Black)
+            .remove(0, 7)
+            .set_turn(Color::Black)
+            .build()
+            .unwrap();
+
+        if g.material_signature() != "KQvK" || g.get_turn_owner() != Color::Black {
+            panic!("Wrong game: {}", g.material_signature());
+        }
+        if g.get_game_state() != GameState::Running {
+            panic!("It should be a normal game, got {:?}", g.get_game_state());
+        }
+
+        if Game::editor()
+            .place(4, 0, 'K', Color::White)
+            .build()
+            .is_ok()
+        {
+            panic!("Black has no king.");
+        }
+        if Game::editor()
+            .place(4, 0, 'K', Color::White)
+            .place(4, 7, 'K', Color::Black)
+            .place(8, 0, 'Q', Color::White)
+            .build()
+            .is_ok()
+        {
+            panic!("The queen is outside the board.");
+        }
+    }
+
+    #[test]
+    fn test_reversible_history() {
+        let mut g = Game::new();
+        g.make_move((6, 0), (5, 2));
+        g.make_move((6, 7), (5, 5));
+        g.make_move((5, 2), (4, 4));
+
+        let expected = [((6, 0), (5, 2)), ((6, 7), (5, 5)), ((5, 2), (4, 4))];
+        let history: Vec<_> = g
+            .reversible_history()
+            .iter()
+            .map(|m| (m.from, m.to))
+            .collect();
+        if history != expected {
+            panic!("Wrong history: {:?}", history);
+        }
+
+        // The knight takes a pawn.
+        g.make_move((5, 5), (4, 3));
+        if !g.make_move((4, 4), (3, 6)) {
+            panic!("The knight should be able to take.");
+        }
+        if !g.reversible_history().is_empty() {
+            panic!("It should be empty: {:?}", g.reversible_history());
+        }
+
+        g.make_move((4, 3), (5, 5));
+        if g.reversible_history().len() != 1 {
+            panic!("Only the last move should be there.");
+        }
+    }
+
+    #[test]
+    fn test_is_legal_position() {
+        let mut t = TEST_TEMPLATE;
+        t[6 + 3 * 8] = 'R';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+
+        // Black is in check, so it can not be White's turn.
+        if Game::from_parts(b.clone(), Color::White, 1).is_legal_position() {
+            panic!("Black would have moved into check.");
+        }
+        if !Game::from_parts(b, Color::Black, 1).is_legal_position() {
+            panic!("Black being in check on its own turn is fine.");
+        }
+        if !Game::new().is_legal_position() {
+            panic!("The start is legal.");
+        }
+    }
+
+    #[test]
+    fn test_color_index_and_opposite() {
+        if Color::White.index() != 0 || Color::Black.index() != 1 {
+            panic!("White is 0 and Black is 1.");
+        }
+        if Color::White.opposite() != Color::Black || Color::Black.opposite() != Color::White {
+            panic!("Wrong opposite.");
+        }
+        for color in [Color::White, Color::Black] {
+            if color.opposite().opposite() != color {
+                panic!("Going back and forth should not change anything.");
+            }
+        }
+
+        // The pawns capture the opposite color.
+        let mut t = TEST_TEMPLATE;
+        t[3 + 3 * 8] = 'p';
+        t[4 + 4 * 8] = 'p';
+        t[2 + 4 * 8] = 'p';
+        let colors = COLOR_TEMPLATE | 1 << (2 + 4 * 8);
+        let g = Game::from_parts(Game::make_board(t, colors).unwrap(), Color::White, 1);
+        let moves = g.get_moves(3, 3).unwrap();
+        if !moves.contains_key(&(4 + 4 * 8)) || moves.contains_key(&(2 + 4 * 8)) {
+            panic!("The pawn should only capture Black.");
+        }
+    }
+
+    #[test]
+    fn test_find_pieces() {
+        let mut t = TEST_TEMPLATE;
+        t[4 + 8] = 'p';
+        t[4 + 3 * 8] = 'p';
+        t[4 + 6 * 8] = 'p';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let g = Game::from_parts(b, Color::White, 1);
+
+        if g.find_pieces(Color::White, 'p') != vec![(4, 1), (4, 3)] {
+            panic!("Wrong pawns: {:?}", g.find_pieces(Color::White, 'p'));
+        }
+        if g.find_pieces(Color::Black, 'K') != vec![(6, 7)] {
+            panic!("Wrong king: {:?}", g.find_pieces(Color::Black, 'K'));
+        }
+        if !g.find_pieces(Color::White, 'Q').is_empty() {
+            panic!("There are no queens.");
+        }
+    }
+
+    #[test]
+    fn test_pawn_structure() {
+        let mut t = TEST_TEMPLATE;
+        t[8] = 'p';
+        t[4 + 8] = 'p';
+        t[4 + 3 * 8] = 'p';
+        t[5 + 8] = 'p';
+        t[7 + 6 * 8] = 'p';
+        t[3 + 6 * 8] = 'p';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let g = Game::from_parts(b, Color::White, 1);
+
+        if g.doubled_pawns(Color::White) != vec![(4, 1), (4, 3)] {
+            panic!("Wrong doubled pawns: {:?}", g.doubled_pawns(Color::White));
+        }
+        if g.isolated_pawns(Color::White) != vec![(0, 1)] {
+            panic!("Wrong isolated pawns: {:?}", g.isolated_pawns(Color::White));
+        }
+        // The d7 pawn guards the e-file, but nothing stops the a-pawn or the f-pawn.
+        if g.passed_pawns(Color::White) != vec![(0, 1), (5, 1)] {
+            panic!("Wrong passed pawns: {:?}", g.passed_pawns(Color::White));
+        }
+        // Black's h-pawn has nothing in front of it, going down.
+        if g.passed_pawns(Color::Black) != vec![(7, 6)] {
+            panic!(
+                "Wrong black passed pawns: {:?}",
+                g.passed_pawns(Color::Black)
+            );
+        }
+    }
+
+    #[test]
+    fn test_analyze_mate_in_one() {
+        let mut t = TEST_TEMPLATE;
+        t[3 + 3 * 8] = 'R';
+        t[5 + 6 * 8] = 'p';
+        t[6 + 6 * 8] = 'p';
+        t[7 + 6 * 8] = 'p';
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
+        let g = Game::from_parts(b, Color::White, 1);
+
+        let mate = ChessMove {
+            from: (3, 3),
+            to: (3, 7),
+            promotion: None,
+        };
+        let result = g.analyze(2);
+        if result.best_move != Some(mate) || result.pv != vec![mate] {
+            panic!("Expected the back row mate, got {:?}", result);
+        }
+        if result.score != Score::Mate(1) {
+            panic!("Expected mate in one, got {:?}", result.score);
+        }
+        if result.stats.nodes < 2 {
+            panic!("It must have looked at something.");
+        }
+
+        // The black king can only go to g8, and then the rook mates.
+        let mut t = ['0'; 64];
+        t[0] = 'R';
+        t[6 + 5 * 8] = 'K';
+        t[7 + 7 * 8] = 'K';
+        let b = Game::make_board(t, 1 | 1 << (6 + 5 * 8)).unwrap();
+        let g = Game::from_parts(b, Color::Black, 1);
+        if g.analyze(2).score != Score::Mate(-1) {
+            panic!("Black should see the mate coming: {:?}", g.analyze(2));
+        }
+    }
+
+    #[test]
+    fn test_analyze_finished_game() {
+        let mut g = Game::new();
+        for san in ["f3", "e5", "g4", "Qh4#"] {
+            g.make_move_san(san).unwrap();
+        }
+        let result = g.analyze(2);
+        if result.best_move.is_some() || result.score != Score::Mate(0) {
+            panic!("White is already mated, got {:?}", result);
+        }
+
+        // Black has no moves but isn't in check.
+        let g = Game::editor()
+            .place(7, 0, 'K', Color::White)
+            .place(1, 5, 'Q', Color::White)
+            .place(0, 7, 'K', Color::Black)
+            .set_turn(Color::Black)
+            .build()
+            .unwrap();
+        if g.get_game_state() != GameState::Stalemate {
+            panic!("Expected stalemate, got {:?}", g.get_game_state());
+        }
+        let result = g.analyze(2);
+        if result.best_move.is_some() || result.score != Score::Centipawns(0) {
+            panic!("A stalemate is worth nothing, got {:?}", result);
+        }
+    }
+
+    #[test]
+    fn test_perft_stats() {
+        let (counts, stats) = Game::new().perft_stats(3);
+        if counts != vec![20, 400, 8902] {
+            panic!("Wrong counts: {:?}", counts);
+        }
+        if stats.nodes != 8902 {
+            panic!("Expected 8902 nodes, got {}", stats.nodes);
+        }
+    }
+
+    #[test]
+    fn test_quiescence() {
+        // The pawn on d5 is free at depth 1, unless you look at the recapture from e6.
+        let g = Game::from_placements(
+            &[
+                (0, 0, 'K', Color::White),
+                (3, 0, 'Q', Color::White),
+                (7, 7, 'K', Color::Black),
+                (3, 4, 'p', Color::Black),
+                (4, 5, 'p', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        let result = g.analyze(1);
+        if result.best_move.map(|m| (m.from, m.to)) == Some(((3, 0), (3, 4))) {
+            panic!("The queen should not take a defended pawn: {:?}", result);
+        }
+        if result.stats.q_nodes == 0 {
+            panic!("The search should have looked at the recapture.");
+        }
+    }
+
+    #[test]
+    fn test_transposition_table() {
+        let g = Game::editor()
+            .place(1, 0, 'K', Color::White)
+            .place(3, 3, 'R', Color::White)
+            .place(6, 7, 'K', Color::Black)
+            .build()
+            .unwrap();
+
+        let plain = g.analyze(4);
+        let mut tt = TranspositionTable::new(1 << 12);
+        let cached = g.analyze_tt(4, &mut tt);
+
+        if plain.best_move != cached.best_move || plain.score != cached.score {
+            panic!("The table changed the result: {:?} {:?}", plain, cached);
+        }
+        if cached.stats.nodes >= plain.stats.nodes {
+            panic!(
+                "The table should save work: {} vs {}",
+                cached.stats.nodes, plain.stats.nodes
+            );
+        }
+        if g.zobrist() == g.mirror_vertical().zobrist() {
+            panic!("Different positions should hash differently.");
+        }
+    }
+
+    #[test]
+    fn test_from_placements() {
+        let g = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (0, 0, 'R', Color::White),
+                (4, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        let moves = g.legal_moves();
+        // 5 king moves, castling, and 7 + 3 rook moves.
+        if moves.len() != 16 {
+            panic!("Expected 16 moves, got {:?}", moves);
+        }
+        if !moves.contains(&((4, 0), (2, 0))) || !moves.contains(&((0, 0), (0, 7))) {
+            panic!("Missing castling or the rook going all the way up.");
+        }
+
+        if Game::from_placements(&[(4, 0, 'K', Color::White)], Color::White).is_ok() {
+            panic!("Black needs a king.");
+        }
+    }
+
+    #[test]
+    fn test_promotion_mates() {
+        let setup = [
+            (1, 0, 'K', Color::White),
+            (0, 6, 'p', Color::White),
+            (7, 7, 'K', Color::Black),
+            (6, 6, 'p', Color::Black),
+            (7, 6, 'p', Color::Black),
+        ];
+
+        let mut g = Game::from_placements(&setup, Color::White).unwrap();
+        if !g.make_move((0, 6), (0, 7)) || g.get_game_state() != GameState::Promote {
+            panic!("The pawn should be waiting to promote.");
+        }
+        if !g.promote((0, 7), 'Q') {
+            panic!("The promotion failed.");
+        }
+        if g.get_game_state() != GameState::CheckMate {
+            panic!("The new queen mates, got {:?}", g.get_game_state());
+        }
+
+        // A knight does not even give check.
+        let mut g = Game::from_placements(&setup, Color::White).unwrap();
+        let knight = ChessMove {
+            from: (0, 6),
+            to: (0, 7),
+            promotion: Some('N'),
+        };
+        if g.make(knight).is_err() || g.get_game_state() != GameState::Running {
+            panic!("Expected Running, got {:?}", g.get_game_state());
+        }
+    }
+
+    #[test]
+    fn test_promotion_square() {
+        let mut g = Game::from_placements(
+            &[
+                (1, 0, 'K', Color::White),
+                (3, 6, 'p', Color::White),
+                (6, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        if g.promotion_square().is_some() {
+            panic!("Nothing is promoting yet.");
+        }
+        g.make_move((3, 6), (3, 7));
+        if g.promotion_square() != Some((3, 7)) {
+            panic!("Expected d8, got {:?}", g.promotion_square());
+        }
+        g.promote((3, 7), 'R');
+        if g.promotion_square().is_some() {
+            panic!("It is done promoting.");
+        }
+    }
+
+    #[test]
+    fn test_set_turn() {
+        let mut g = Game::from_placements(
+            &[
+                (1, 0, 'K', Color::White),
+                (6, 3, 'R', Color::White),
+                (6, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        if g.get_game_state() != GameState::Running {
+            panic!("White is not in check.");
+        }
+
+        g.set_turn(Color::Black);
+        if g.get_turn_owner() != Color::Black || g.get_game_state() != GameState::Check {
+            panic!("Black is in check, got {:?}", g.get_game_state());
+        }
+
+        g.set_turn(Color::White);
+        if g.get_game_state() != GameState::Running {
+            panic!("Back to White, got {:?}", g.get_game_state());
+        }
+    }
+
+    #[test]
+    fn test_categorized_moves() {
+        let g = Game::from_placements(
+            &[
+                (1, 0, 'K', Color::White),
+                (4, 1, 'p', Color::White),
+                (3, 2, 'p', Color::Black),
+                (6, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        let (quiet, captures) = g.categorized_moves(4, 1);
+        if quiet != vec![(4, 2), (4, 3)] || captures != vec![(3, 2)] {
+            panic!("Wrong split: {:?} {:?}", quiet, captures);
+        }
+
+        // En passant lands on an empty square, but it still captures.
+        let mut g = Game::new();
+        g.make_move((4, 1), (4, 3));
+        g.make_move((0, 6), (0, 5));
+        g.make_move((4, 3), (4, 4));
+        g.make_move((3, 6), (3, 4));
+        let (quiet, captures) = g.categorized_moves(4, 4);
+        if quiet != vec![(4, 5)] || captures != vec![(3, 5)] {
+            panic!("Wrong en passant split: {:?} {:?}", quiet, captures);
+        }
+    }
+
+    #[test]
+    fn test_crazyhouse_drop() {
+        let mut g = Game::from_placements(
+            &[
+                (1, 0, 'K', Color::White),
+                (0, 3, 'R', Color::White),
+                (0, 6, 'Q', Color::Black),
+                (6, 7, 'K', Color::Black),
+                (7, 6, 'p', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        g.set_rules(RuleSet {
+            crazyhouse: true,
+            ..Default::default()
+        });
+
+        g.make_move((0, 3), (0, 6));
+        if g.hand(Color::White) != vec!['Q'] {
+            panic!("White should have the queen: {:?}", g.hand(Color::White));
+        }
+        if g.drop('Q', (4, 4)) {
+            panic!("Black has nothing to drop.");
+        }
+        g.make_move((7, 6), (7, 5));
+
+        if g.drop('Q', (7, 5)) || g.drop('R', (6, 4)) {
+            panic!("Can not drop on a piece, or drop something you do not have.");
+        }
+        if !g.drop('Q', (6, 4)) {
+            panic!("The drop failed.");
+        }
+        if g.get_game_state() != GameState::Check || !g.hand(Color::White).is_empty() {
+            panic!("The queen should give check, got {:?}", g.get_game_state());
+        }
+    }
+
+    #[test]
+    fn test_moves_with_source() {
+        let g = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (7, 0, 'R', Color::White),
+                (3, 3, 'Q', Color::White),
+                (4, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        // The queen only has one entry, so everything comes from it.
+        let queen = g.get_piece_at(3, 3).unwrap().moves_with_source(3, 3, &g);
+        if queen.len() != 27 || queen.iter().any(|&(_, i)| i != 0) {
+            panic!("Wrong queen moves: {:?}", queen);
+        }
+
+        // Castling is the second entry for the king.
+        let king = g.get_piece_at(4, 0).unwrap().moves_with_source(4, 0, &g);
+        if !king.contains(&(6, 1)) || !king.contains(&(5, 0)) || king.contains(&(6, 0)) {
+            panic!("Wrong king moves: {:?}", king);
+        }
+    }
+
+    #[test]
+    fn test_maximum_slide() {
+        let g = Game::from_placements(
+            &[
+                (0, 0, 'R', Color::White),
+                (7, 7, 'K', Color::White),
+                (7, 5, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        let mut rook = g.get_piece_at(0, 0).unwrap().clone();
+
+        // Nothing in the way, so it goes all the way across.
+        let moves = rook.get_all_possible_moves(0, 0, &g);
+        if !moves.contains_key(&7) || !moves.contains_key(&56) {
+            panic!("The rook should reach the other edges: {:?}", moves.keys());
+        }
+
+        rook.moves[0].maximum_slide = Some(0);
+        if !rook.get_all_possible_moves(0, 0, &g).is_empty() {
+            panic!("A maximum slide of 0 should go nowhere.");
+        }
+    }
+
+    #[test]
+    fn test_pack_square() {
+        for sq in 0..64 {
+            let (col, row) = unpack_square(sq);
+            if pack_square(col, row) != sq {
+                panic!("{} came back as {}", sq, pack_square(col, row));
+            }
+        }
+        if unpack_square(pack_square(3, 6)) != (3, 6) || unpack_square(12) != (4, 1) {
+            panic!("The squares got mixed up.");
+        }
+    }
+
+    // Every move, tried out the slow way.
+    fn brute_force_moves(g: &Game) -> Vec<((u8, u8), (u8, u8))> {
+        let mut all = Vec::new();
+        for i in 0..64_u8 {
+            let from = unpack_square(i);
+            if let Some(p) = g.get_piece_at(from.0, from.1) {
+                if p.color != g.get_turn_owner() {
+                    continue;
+                }
+                for m in &p.moves {
+                    for (t, effects) in m.prune(g, from) {
+                        let to = unpack_square(t);
+                        if g.is_safe_move(from, to, &effects, p.color) && !all.contains(&(from, to))
+                        {
+                            all.push((from, to));
+                        }
+                    }
+                }
+            }
+        }
+        all.sort();
+        all
+    }
+
+    #[test]
+    fn test_check_evasions() {
+        // A rook check that can be blocked or captured.
+        let blocked = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (0, 1, 'Q', Color::White),
+                (6, 2, 'N', Color::White),
+                (4, 7, 'R', Color::Black),
+                (0, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        // A double check, where only the king can move.
+        let double = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (3, 1, 'Q', Color::White),
+                (4, 7, 'R', Color::Black),
+                (7, 3, 'B', Color::Black),
+                (0, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        // A pawn check that can only be captured en passant.
+        let mut passant = Game::from_placements(
+            &[
+                (4, 3, 'K', Color::White),
+                (4, 4, 'p', Color::White),
+                (3, 6, 'p', Color::Black),
+                (0, 7, 'K', Color::Black),
+            ],
+            Color::Black,
+        )
+        .unwrap();
+        passant.make_move((3, 6), (3, 4));
+        if !passant.legal_moves().contains(&((4, 4), (3, 5))) {
+            panic!("En passant should get out of check.");
+        }
+
+        for g in [blocked, double, passant] {
+            let mut moves = g.legal_moves();
+            moves.sort();
+            if g.get_game_state() != GameState::Check || moves != brute_force_moves(&g) {
+                panic!("Got {:?}, expected {:?}", moves, brute_force_moves(&g));
+            }
+        }
+    }
+
+    #[test]
+    fn test_status() {
+        let mut g = Game::new();
+        let s = g.status();
+        if s.last_move.is_some() || s.halfmove_clock != 0 || s.turn_count != 1 || s.in_check {
+            panic!("Wrong status at the start: {:?}", s);
+        }
+
+        g.make_move((6, 0), (5, 2));
+        g.make_move((6, 7), (5, 5));
+        g.make_move((4, 1), (4, 3));
+        g.make_move((1, 7), (2, 5));
+        let s = g.status();
+        if s.turn_owner != g.get_turn_owner()
+            || s.game_state != g.get_game_state()
+            || s.halfmove_clock != g.halfmove_clock()
+            || s.in_check != g.is_check()
+            || s.can_claim_draw != g.can_claim_draw()
+            || s.last_move != g.last_move()
+        {
+            panic!("The status does not match: {:?}", s);
+        }
+        if s.halfmove_clock != 1
+            || s.turn_count != 3
+            || s.last_move.map(|m| m.to) != Some((2, 5))
+            || s.can_claim_draw
+        {
+            panic!("Wrong status: {:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_corner_stalemate() {
+        let start = Game::from_placements(
+            &[
+                (2, 6, 'K', Color::White),
+                (1, 2, 'Q', Color::White),
+                (0, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        // Qb6 takes every square from the king, but does not attack it.
+        let mut stale = start.clone();
+        stale.make_move((1, 2), (1, 5));
+        if stale.get_game_state() != GameState::Stalemate || !stale.is_stalemate() {
+            panic!("Should be stalemate, got {:?}", stale.get_game_state());
+        }
+
+        // Qb7 does, and the king can not take it.
+        let mut mate = start.clone();
+        mate.make_move((1, 2), (1, 6));
+        if mate.get_game_state() != GameState::CheckMate || !mate.is_checkmate() {
+            panic!("Should be check mate, got {:?}", mate.get_game_state());
+        }
     }
 
-    fn has_moves(&self) -> bool {
-        for i in 0..64 {
-            if let Some(p) = self.get_piece_at(i % 8, i >> 3) {
-                if p.color != self.turn_owner {
-                    continue;
-                }
-            } else {
-                continue;
-            }
+    #[test]
+    fn test_threats_to() {
+        let g = Game::from_placements(
+            &[
+                (7, 0, 'K', Color::White),
+                (3, 0, 'Q', Color::White),
+                (6, 3, 'B', Color::Black),
+                (0, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        if g.threats_to(Color::White) != vec![((6, 3), (3, 0))] {
+            panic!(
+                "The bishop should attack the queen: {:?}",
+                g.threats_to(Color::White)
+            );
+        }
+        // Same diagonal, so the queen attacks right back.
+        if g.threats_to(Color::Black) != vec![((3, 0), (6, 3))] {
+            panic!(
+                "The queen should attack the bishop: {:?}",
+                g.threats_to(Color::Black)
+            );
+        }
+    }
 
-            if let Some(m) = self.get_moves(i % 8, i >> 3) {
-                if m.len() > 0 {
-                    return true;
-                }
-            }
+    #[test]
+    fn test_branching_factor() {
+        let g = Game::new();
+        // 20 moves each, and nobody can get in the way of the other yet.
+        if g.branching_factor(1) != 20.0 || g.branching_factor(2) != 20.0 {
+            panic!(
+                "Expected 20.0, got {} and {}",
+                g.branching_factor(1),
+                g.branching_factor(2)
+            );
+        }
+        if g.branching_factor(0) != 0.0 {
+            panic!("Depth 0 has nothing to branch.");
         }
-        false
     }
 
-    fn in_check(&self) -> bool {
-        for i in 0..64 {
-            if let Some(p) = self.get_piece_at(i % 8, i >> 3) {
-                if p.is_crucial
-                    && p.color == self.turn_owner
-                    && !self.is_safe_position(i % 8, i >> 3, p.color)
-                {
-                    return true;
-                }
+    #[test]
+    fn test_render_empty_board() {
+        let g = Game::from_parts(std::array::from_fn(|_| None), Color::White, 1);
+        let empty_row = "   \x1b[0m".repeat(8);
+
+        for unicode in [false, true] {
+            let out = g.render(&RenderOptions {
+                light_square: String::new(),
+                unicode,
+                ..Default::default()
+            });
+            let lines: Vec<&str> = out.lines().collect();
+            if lines.len() != 8 || lines.iter().any(|l| *l != empty_row) {
+                panic!("Expected 8 empty rows, got {:?}", lines);
             }
         }
-        false
     }
 
-    fn increment_turn(&mut self) {
-        match self.turn_owner {
-            Color::White => {
-                self.turn_owner = Color::Black;
+    #[test]
+    fn test_move_command() {
+        let g = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (7, 0, 'R', Color::White),
+                (0, 0, 'R', Color::White),
+                (4, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        if g.move_command((4, 0), (6, 0)).as_deref() != Some("O-O")
+            || g.move_command((4, 0), (2, 0)).as_deref() != Some("O-O-O")
+        {
+            panic!("Castling should have its commands.");
+        }
+        if g.move_command((4, 0), (5, 0)).is_some() || g.move_command((4, 0), (4, 2)).is_some() {
+            panic!("Normal and impossible moves have no command.");
+        }
+    }
+
+    #[test]
+    fn test_is_promotion_move() {
+        let g = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (0, 6, 'p', Color::White),
+                (3, 1, 'p', Color::White),
+                (4, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        if !g.is_promotion_move((0, 6), (0, 7)) {
+            panic!("a7-a8 should promote.");
+        }
+        if g.is_promotion_move((3, 1), (3, 2)) || g.is_promotion_move((0, 6), (1, 7)) {
+            panic!("Normal and impossible moves do not promote.");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "random")]
+    fn test_random_move() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let g = Game::new();
+        let first = g.random_move(&mut StdRng::seed_from_u64(7));
+        let again = g.random_move(&mut StdRng::seed_from_u64(7));
+        if first.is_none() || first != again || !g.legal_moves().contains(&first.unwrap()) {
+            panic!(
+                "Expected the same legal move twice, got {:?} and {:?}",
+                first, again
+            );
+        }
+
+        let empty = Game::from_parts(std::array::from_fn(|_| None), Color::White, 1);
+        if empty.random_move(&mut StdRng::seed_from_u64(7)).is_some() {
+            panic!("There is nothing to move.");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "random")]
+    fn test_play_random_game() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        // Short games, since a hundred long ones take forever without optimizations.
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let mut g = Game::new();
+            let state = g.play_random_game(&mut rng, 20);
+            if state != g.get_game_state() || state == GameState::Promote {
+                panic!("Ended up in {:?}", state);
             }
-            Color::Black => {
-                self.turn_owner = Color::White;
-                self.turn_count += 1;
+            if !g.is_legal_position() {
+                panic!("The random game left an impossible position.");
             }
         }
+    }
 
-        if self.in_check() {
-            self.game_state = GameState::Check;
-        } else {
-            self.game_state = GameState::Running;
+    #[test]
+    fn test_just_move_empty_square() {
+        let mut g = Game::new();
+        let before = g.to_char_array();
+
+        if g.just_move((4, 4), (4, 5)) {
+            panic!("There is nothing on e5 to move.");
+        }
+        // A castling effect with no rook to move should not crash either.
+        g.just_execute_move(
+            (4, 3),
+            (4, 4),
+            &vec![Effect::Move(
+                Position::Relative((3, 0)),
+                Position::Relative((1, 0)),
+            )],
+        );
+        if g.to_char_array() != before {
+            panic!("Nothing should have moved.");
+        }
+    }
+
+    #[test]
+    fn test_captures_of_square() {
+        let g = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (3, 1, 'N', Color::White),
+                (7, 3, 'R', Color::White),
+                (0, 4, 'B', Color::Black),
+                (4, 3, 'p', Color::Black),
+                (0, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        // The knight attacks e4 too, but it is pinned to the king.
+        if !g.attackers_of(4, 3, Color::White).contains(&(3, 1)) {
+            panic!("The knight should still attack e4.");
+        }
+        if g.captures_of_square(4, 3) != vec![(7, 3)] {
+            panic!("Only the rook can take: {:?}", g.captures_of_square(4, 3));
+        }
+
+        // En passant takes the pawn without landing on it.
+        let mut passant = Game::new();
+        passant.make_move((4, 1), (4, 3));
+        passant.make_move((0, 6), (0, 5));
+        passant.make_move((4, 3), (4, 4));
+        passant.make_move((3, 6), (3, 4));
+        if passant.captures_of_square(3, 4) != vec![(4, 4)] {
+            panic!(
+                "En passant should count: {:?}",
+                passant.captures_of_square(3, 4)
+            );
+        }
+    }
+
+    #[test]
+    fn test_material() {
+        let m = Game::new().material();
+        for color in [Color::White, Color::Black] {
+            for (rank, count) in [('p', 8), ('R', 2), ('N', 2), ('B', 2), ('Q', 1), ('K', 1)] {
+                if m.get(&(color, rank)) != Some(&count) {
+                    panic!(
+                        "Expected {} {:?} {}, got {:?}",
+                        count,
+                        color,
+                        rank,
+                        m.get(&(color, rank))
+                    );
+                }
+            }
+        }
+        if m.len() != 12 {
+            panic!("There should only be 12 kinds of pieces: {:?}", m);
         }
+    }
 
-        if !self.has_moves() {
-            self.game_state = match self.game_state {
-                GameState::Check => GameState::CheckMate,
-                _ => GameState::Stalemate,
+    #[test]
+    fn test_make_move_with() {
+        let start = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (0, 6, 'p', Color::White),
+                (4, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        let mut g = start.clone();
+        if !g.make_move_with((0, 6), (0, 7), |ranks| {
+            if !ranks.contains(&'N') {
+                panic!("Should be able to pick a knight: {:?}", ranks);
             }
+            'N'
+        }) {
+            panic!("The promotion failed.");
+        }
+        if g.get_piece_at(0, 7).map(|p| p.rank) != Some('N')
+            || g.get_game_state() == GameState::Promote
+        {
+            panic!("There should be a knight on a8.");
+        }
+
+        // Kings are not on the list, so nothing happens.
+        let mut g = start.clone();
+        if g.make_move_with((0, 6), (0, 7), |_| 'K') || g.get_piece_at(0, 6).is_none() {
+            panic!("Promoting to a king should fail.");
+        }
+        if !g.make_move_with((4, 0), (4, 1), |_| panic!("Nothing to promote.")) {
+            panic!("Normal moves should work too.");
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    const test_template: [char; 64] = [
-        '0', 'K', '0', '0', '0', '0', '0', '0', //
-        '0', '0', '0', '0', '0', '0', '0', '0', //
-        '0', '0', '0', '0', '0', '0', '0', '0', //
-        '0', '0', '0', '0', '0', '0', '0', '0', //
-        '0', '0', '0', '0', '0', '0', '0', '0', //
-        '0', '0', '0', '0', '0', '0', '0', '0', //
-        '0', '0', '0', '0', '0', '0', '0', '0', //
-        '0', '0', '0', '0', '0', '0', 'K', '0', //
-    ];
-    const color_template: u64 = 0x00000000FFFFFFFF;
     #[test]
-    fn make_and_print() {
+    fn test_square_predicates() {
         let g = Game::new();
-        g.print_board();
+
+        if !g.is_empty(4, 4) || g.is_empty(4, 1) || g.is_empty(8, 0) {
+            panic!("Wrong empty squares.");
+        }
+        if !g.is_enemy(4, 6, Color::White)
+            || g.is_enemy(4, 1, Color::White)
+            || g.is_enemy(4, 4, Color::White)
+        {
+            panic!("Wrong enemies.");
+        }
+        if !g.is_friend(4, 1, Color::White)
+            || g.is_friend(4, 6, Color::White)
+            || g.is_friend(4, 9, Color::White)
+        {
+            panic!("Wrong friends.");
+        }
     }
 
     #[test]
-    fn display_moves() {
+    fn test_ray_between() {
         let g = Game::new();
-        g.print_moves(4, 1);
-        g.print_moves(5, 1);
+
+        if g.ray_between((0, 3), (3, 3)) != Some(vec![(0, 3), (1, 3), (2, 3), (3, 3)]) {
+            panic!("Wrong horizontal ray: {:?}", g.ray_between((0, 3), (3, 3)));
+        }
+        if g.ray_between((5, 4), (2, 1)) != Some(vec![(5, 4), (4, 3), (3, 2), (2, 1)]) {
+            panic!("Wrong diagonal ray: {:?}", g.ray_between((5, 4), (2, 1)));
+        }
+        if g.ray_between((1, 0), (2, 2)).is_some() || g.ray_between((4, 4), (4, 4)).is_some() {
+            panic!("Those are not lines.");
+        }
     }
 
     #[test]
-    fn danger_zone() {
+    fn test_get_moves_in_check() {
+        let g = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (0, 2, 'R', Color::White),
+                (1, 4, 'B', Color::White),
+                (1, 0, 'N', Color::White),
+                (4, 7, 'R', Color::Black),
+                (0, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        let targets = |col, row| {
+            let mut t: Vec<(u8, u8)> = g
+                .get_moves(col, row)
+                .unwrap()
+                .into_keys()
+                .map(unpack_square)
+                .collect();
+            t.sort();
+            t
+        };
+
+        if targets(0, 2) != vec![(4, 2)] {
+            panic!("The rook can only block on e3: {:?}", targets(0, 2));
+        }
+        if targets(1, 4) != vec![(4, 1), (4, 7)] {
+            panic!(
+                "The bishop can block on e2 or take on e8: {:?}",
+                targets(1, 4)
+            );
+        }
+        if !targets(1, 0).is_empty() {
+            panic!("The knight can not help: {:?}", targets(1, 0));
+        }
+    }
+
+    #[test]
+    fn test_legal_move_count() {
+        if Game::new().legal_move_count() != 20 {
+            panic!("Expected 20, got {}", Game::new().legal_move_count());
+        }
+
+        let mate = Game::from_placements(
+            &[
+                (2, 5, 'K', Color::White),
+                (1, 6, 'Q', Color::White),
+                (0, 7, 'K', Color::Black),
+            ],
+            Color::Black,
+        )
+        .unwrap();
+        if mate.get_game_state() != GameState::CheckMate || mate.legal_move_count() != 0 {
+            panic!("Check mate has no moves, got {}", mate.legal_move_count());
+        }
+    }
+
+    #[test]
+    fn test_promoted_rook_can_not_castle() {
+        let mut g = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (0, 0, 'R', Color::White),
+                (7, 0, 'R', Color::White),
+                (4, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        if g.can_castle(Color::White) != (true, true) {
+            panic!("Both rooks are fine so far.");
+        }
+
+        // Pretend the a1 rook was a pawn once, without it ever having moved as a rook.
+        let mut rook = g.get_piece_at(0, 0).unwrap().clone();
+        rook.promoted = true;
+        g.put(0, 0, Some(rook));
+        if g.can_castle(Color::White) != (true, false) || g.make_move((4, 0), (2, 0)) {
+            panic!("The promoted rook should not be able to castle.");
+        }
+    }
+
+    #[test]
+    fn test_is_defended() {
+        let g = Game::from_placements(
+            &[
+                (7, 0, 'K', Color::White),
+                (3, 3, 'N', Color::White),
+                (4, 2, 'p', Color::White),
+                (0, 4, 'N', Color::White),
+                (7, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        if !g.is_defended(3, 3, Color::White) {
+            panic!("The pawn defends the knight on d4.");
+        }
+        if g.is_defended(0, 4, Color::White) {
+            panic!("Nothing defends the knight on a5.");
+        }
+        if !g.is_defended(5, 3, Color::White) || g.is_defended(3, 3, Color::Black) {
+            panic!("The pawn covers f4 too, and black defends nothing.");
+        }
+    }
+
+    #[test]
+    fn test_pgn_reader() {
+        let pgn = r#"[Event "Scholar's mate"]
+[White "Someone"]
+[Result "1-0"]
+
+1. e4 e5 2. Bc4 {the bishop} Nc6 3. Qh5 Nf6?? (3... g6 4. Qf3) 4. Qxf7# 1-0
+
+[Event "Knights"]
+[Result "1/2-1/2"]
+
+1.Nf3 Nf6 2.Ng1 $2 Ng8 ; back home
+3.d4 d5 4.c4 dxc4 1/2-1/2
+"#;
+        let games: Vec<Result<GameRecord, String>> = PgnReader::new(pgn.as_bytes()).collect();
+        if games.len() != 2 || games.iter().any(|g| g.is_err()) {
+            panic!(
+                "Both games should parse: {:?}",
+                games.iter().map(|g| g.as_ref().err()).collect::<Vec<_>>()
+            );
+        }
+
+        let mate = games[0].as_ref().unwrap();
+        if mate.header("White") != Some("Someone")
+            || mate.result.as_deref() != Some("1-0")
+            || mate.moves.len() != 7
+            || mate.game.get_game_state() != GameState::CheckMate
+        {
+            panic!("The first game is wrong.");
+        }
+
+        let knights = games[1].as_ref().unwrap();
+        if knights.header("Event") != Some("Knights")
+            || knights.result.as_deref() != Some("1/2-1/2")
+            || knights.moves.len() != 8
+            || knights.game.get_piece_at(2, 3).map(|p| p.color) != Some(Color::Black)
+        {
+            panic!("The second game is wrong.");
+        }
+
+        // The ; inside the braces is part of the comment, not the start of another one.
+        let commented = "1. e4 {good; very good} e5 2. Nf3 Nc6 1-0";
+        let game = PgnReader::new(commented.as_bytes())
+            .next()
+            .unwrap()
+            .unwrap();
+        if game.moves.len() != 4 || game.result.as_deref() != Some("1-0") {
+            panic!(
+                "Expected 4 moves and 1-0, got {} and {:?}",
+                game.moves.len(),
+                game.result
+            );
+        }
+
+        let broken = "1. e4 e5 2. Ke3 1-0";
+        if PgnReader::new(broken.as_bytes()).next().unwrap().is_ok() {
+            panic!("Ke3 is not a legal move.");
+        }
+    }
+
+    #[test]
+    fn test_validate_move_list() {
+        let mv = |from, to| ChessMove {
+            from,
+            to,
+            promotion: None,
+        };
         let g = Game::new();
-        let mut i = 0 as u8;
 
-        for o in &g.board {
-            if let Some(p) = o {
-                println!("Testing {} at {},{}", p.rank, i % 8, i >> 3);
-                p.get_danger_zone(i % 8, i >> 3, &g);
-            }
-            i += 1;
+        let good = [mv((4, 1), (4, 3)), mv((4, 6), (4, 4)), mv((6, 0), (5, 2))];
+        if g.validate_move_list(&good) != Ok(()) {
+            panic!("Those moves are fine.");
+        }
+
+        // The e-pawn is blocked after e4 e5.
+        let bad = [
+            mv((4, 1), (4, 3)),
+            mv((4, 6), (4, 4)),
+            mv((4, 3), (4, 4)),
+            mv((6, 7), (5, 5)),
+        ];
+        if g.validate_move_list(&bad) != Err((2, MoveError::IllegalMove)) {
+            panic!(
+                "Expected the third move to fail, got {:?}",
+                g.validate_move_list(&bad)
+            );
+        }
+        if g.to_char_array() != Game::new().to_char_array() {
+            panic!("The game should not have changed.");
         }
     }
 
     #[test]
-    fn test_pawn_move_normal() {
-        let from = (4 as u8, 1 as u8);
-        let to = (4 as u8, 2 as u8);
+    fn test_has_piece_moved() {
+        let mut g = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (7, 0, 'R', Color::White),
+                (4, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        if g.has_piece_moved(7, 0) != Some(false) || g.has_piece_moved(7, 3).is_some() {
+            panic!("The rook has not moved yet.");
+        }
+        g.make_move((7, 0), (7, 3));
+        if g.has_piece_moved(7, 3) != Some(true) || g.has_piece_moved(7, 0).is_some() {
+            panic!("The rook has moved to h4.");
+        }
+    }
+
+    #[test]
+    fn test_stalemate_wins() {
+        let mut g = Game::from_placements(
+            &[
+                (2, 6, 'K', Color::White),
+                (1, 2, 'Q', Color::White),
+                (0, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        g.set_rules(RuleSet {
+            stalemate_wins: true,
+            ..Default::default()
+        });
+
+        // Black has no moves after Qb6, which is a win for Black with these rules.
+        g.make_move((1, 2), (1, 5));
+        if g.get_game_state() != GameState::StalemateWin || !g.is_stalemate() {
+            panic!("Black should have won, got {:?}", g.get_game_state());
+        }
+
+        g.set_rules(RuleSet::default());
+        if g.get_game_state() != GameState::Stalemate {
+            panic!("Back to a normal draw, got {:?}", g.get_game_state());
+        }
+    }
 
+    #[test]
+    fn test_control() {
         let mut g = Game::new();
-        let p = g.get_piece_at(from.0, from.1).unwrap();
-        let m = p.moves[0].prune(&g, from);
+        g.make_move((4, 1), (4, 3));
+        g.make_move((3, 6), (3, 4));
 
-        if m.len() != 1 || !m.contains_key(&(to.0 + to.1 * 8)) {
-            panic!();
+        // e4 attacks d5, and the queen defends it.
+        if g.control(3, 4, Color::Black) != 0 {
+            panic!("d5 should be even, got {}", g.control(3, 4, Color::Black));
+        }
+        g.make_move((1, 0), (2, 2));
+        if g.control(3, 4, Color::Black) != 1 || g.control(3, 4, Color::White) != -1 {
+            panic!(
+                "Nc3 should tip d5 to White, got {}",
+                g.control(3, 4, Color::Black)
+            );
         }
+    }
 
-        println!("Move success: {}", g.make_move(from, to));
-        let a = g.get_piece_at(to.0, to.1);
+    #[test]
+    fn test_cannon() {
+        let mut g = Game::from_placements(
+            &[
+                (7, 1, 'K', Color::White),
+                (0, 2, 'p', Color::White),
+                (1, 0, 'R', Color::Black),
+                (0, 4, 'N', Color::Black),
+                (0, 6, 'p', Color::Black),
+                (7, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        // Moves like a rook, but captures by jumping over exactly one piece.
+        let mut cannon = Piece::new(Color::White, 'R');
+        cannon.rank = 'C';
+        let mut capture = cannon.moves[0].clone();
+        capture.screens = Some(1);
+        cannon.moves[0].can_capture = false;
+        cannon.moves.push(capture);
+        g.put(0, 0, Some(cannon));
+
+        let mut moves: Vec<u8> = g.get_moves(0, 0).unwrap().into_keys().collect();
+        moves.sort();
+        // a2, and the knight on a5 over the a3 pawn. Not the rook next to it, or the pawn behind two screens.
+        if moves != vec![8, 32] {
+            panic!("Wrong cannon moves: {:?}", moves);
+        }
+        if !g.make_move((0, 0), (0, 4)) || g.get_piece_at(0, 4).map(|p| p.rank) != Some('C') {
+            panic!("The cannon should capture the knight.");
+        }
+    }
 
-        println!("Debug Piece: {:#?}", a);
-        g.print_board();
+    #[test]
+    fn test_reset() {
+        let mut g = Game::new();
+        g.set_rules(RuleSet {
+            castling: false,
+            ..Default::default()
+        });
+        g.make_move((4, 1), (4, 3));
+        g.make_move((3, 6), (3, 4));
+        g.make_move((4, 3), (3, 4));
+        g.make_move((6, 7), (5, 5));
+
+        g.reset();
+        let new = Game::new();
+        if g.to_char_array() != new.to_char_array()
+            || g.status() != new.status()
+            || g.get_rules() != new.get_rules()
+            || g.history != new.history
+            || !g.reversible_history().is_empty()
+            || g.legal_moves() != new.legal_moves()
+        {
+            panic!("The game should be just like a new one.");
+        }
+    }
 
-        if let Some(p) = a {
-            if p.color == Color::White && p.last_moved == Some(1) && p.times_moved == 1 {
-                return;
+    #[test]
+    fn test_square_attacked_after() {
+        let g = Game::from_placements(
+            &[
+                (6, 0, 'K', Color::White),
+                (4, 0, 'R', Color::White),
+                (4, 3, 'N', Color::White),
+                (0, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+
+        // The knight is in the way of the rook, until it moves.
+        if g.square_attacked_after((6, 0), (7, 0), (4, 5), Color::White) != Some(false) {
+            panic!("The knight still blocks the rook from e6.");
+        }
+        if g.square_attacked_after((4, 3), (2, 2), (4, 5), Color::White) != Some(true) {
+            panic!("Nc3 opens the e-file to e6.");
+        }
+        if g.square_attacked_after((4, 3), (4, 5), (4, 5), Color::White)
+            .is_some()
+        {
+            panic!("Knights can not move like that.");
+        }
+    }
+
+    #[test]
+    fn test_pin_aware_moves() {
+        use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..10 {
+            let mut g = Game::new();
+            for _ in 0..30 {
+                let mut moves = g.legal_moves();
+                moves.sort();
+                if moves != brute_force_moves(&g) {
+                    panic!("Got {:?}, expected {:?}", moves, brute_force_moves(&g));
+                }
+                let (from, to) = match moves.choose(&mut rng) {
+                    Some(&mv) => mv,
+                    None => break,
+                };
+                g.make_move(from, to);
+                g.promote_all('Q');
             }
         }
-        panic!();
+
+        // The knight is pinned, so it has to be checked the slow way.
+        let pinned = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (4, 2, 'N', Color::White),
+                (4, 7, 'R', Color::Black),
+                (0, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        let knight = pinned.get_piece_at(4, 2).unwrap();
+        if pinned.can_skip_safety(knight, 4, 2) || !pinned.legal_moves_from(4, 2).is_empty() {
+            panic!("The pinned knight can not move.");
+        }
     }
 
     #[test]
-    fn test_pawn_move_double() {
-        let from = (4 as u8, 1 as u8);
-        let to = (4 as u8, 3 as u8);
-        let to2 = (4 as u8, 5 as u8);
+    fn test_render_highlights() {
+        let g = Game::new();
+        let opts = RenderOptions {
+            light_square: String::new(),
+            ..Default::default()
+        };
+        let highlights = HashSet::from([(4, 1), (4, 3)]);
+        let out = g.render_with_highlights(&highlights, &opts);
+
+        let marker = "\x1b[38;5;11m\x1b[48;5;11m";
+        let lines: Vec<&str> = out.lines().collect();
+        // Row 0 is the last line, and every square starts right after the previous reset.
+        let square = |col: usize, row: usize| lines[7 - row].split("\x1b[0m").nth(col).unwrap();
+        if !square(4, 1).starts_with(marker) || !square(4, 3).starts_with(marker) {
+            panic!("e2 and e4 should be highlighted.");
+        }
+        if out.matches(marker).count() != 2 {
+            panic!("Only two squares should be highlighted.");
+        }
+        if g.render(&opts).contains(marker) {
+            panic!("Nothing should be highlighted by default.");
+        }
+    }
 
+    #[test]
+    fn test_is_checkmated() {
+        // Fool's mate, so white is the one who lost.
         let mut g = Game::new();
-        let p = g.get_piece_at(from.0, from.1).unwrap();
-        let m = p.moves[1].prune(&g, from);
-
-        if m.len() != 1 || !m.contains_key(&(to.0 + to.1 * 8)) {
-            panic!();
+        for (from, to) in [
+            ((5, 1), (5, 2)),
+            ((4, 6), (4, 4)),
+            ((6, 1), (6, 3)),
+            ((3, 7), (7, 3)),
+        ] {
+            g.make_move(from, to);
+        }
+        if !g.is_checkmated(Color::White) || g.is_checkmated(Color::Black) {
+            panic!("White should be mated, and black should not.");
+        }
+        if g.is_stalemated(Color::White) || g.is_stalemated(Color::Black) {
+            panic!("Nobody is in stalemate.");
         }
 
-        println!("Move success: {}", g.make_move(from, to));
-        let a = g.get_piece_at(to.0, to.1);
+        // Black just got stuck, but white is the one to move (on paper).
+        let stuck = Game::from_placements(
+            &[
+                (0, 7, 'K', Color::Black),
+                (1, 5, 'Q', Color::White),
+                (2, 6, 'K', Color::White),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        if !stuck.is_stalemated(Color::Black) || stuck.is_checkmated(Color::Black) {
+            panic!("Black should be stalemated.");
+        }
+        if stuck.is_stalemated(Color::White) {
+            panic!("White can move just fine.");
+        }
+    }
 
-        println!("Debug Piece: {:#?}", a);
-        g.print_board();
+    #[test]
+    fn test_moves_grouped() {
+        let groups = Game::new().moves_grouped();
+        if groups.len() != 10 {
+            panic!("Expected 8 pawns and 2 knights, got {:?}", groups);
+        }
+        for (sq, rank, targets) in &groups {
+            match rank {
+                'p' if targets.len() == 2 && sq.1 == 1 => {}
+                'N' if *targets == vec![(sq.0 - 1, 2), (sq.0 + 1, 2)] => {}
+                _ => panic!("{} at {:?} has the wrong moves: {:?}", rank, sq, targets),
+            }
+        }
+        if groups.iter().map(|(_, _, t)| t.len()).sum::<usize>() != 20 {
+            panic!("The groups should have all 20 moves.");
+        }
+    }
 
-        if let Some(p) = a {
-            if p.color != Color::White || p.last_moved != Some(1) || p.times_moved != 1 {
-                panic!();
+    #[test]
+    fn test_is_threefold_after() {
+        let mut g = Game::new();
+        let dance = [
+            ((6, 0), (5, 2)),
+            ((6, 7), (5, 5)),
+            ((5, 2), (6, 0)),
+            ((5, 5), (6, 7)),
+        ];
+        for (from, to) in dance.iter().cycle().take(7) {
+            if g.is_threefold_after(*from, *to) {
+                panic!("{:?} to {:?} is not the third time yet.", from, to);
             }
-        } else {
-            panic!();
+            g.make_move(*from, *to);
         }
 
-        // Also make sure it can not move in such a way twice
-        println!("Move success (should be false): {}", g.make_move(from, to));
+        // Black's knight going home is the third time we see the starting position.
+        let before = g.clone();
+        if !g.is_threefold_after((5, 5), (6, 7)) {
+            panic!("Going back should allow a draw claim.");
+        }
+        if g.is_threefold_after((4, 6), (4, 4)) || g.is_threefold_after((4, 6), (4, 1)) {
+            panic!("Other moves (and impossible ones) do not repeat anything.");
+        }
+        if g.to_char_array() != before.to_char_array() || g.is_threefold_now() {
+            panic!("Looking ahead should not change the game.");
+        }
+        g.make_move((5, 5), (6, 7));
+        if !g.is_threefold_now() {
+            panic!("The lookahead and the real thing disagree.");
+        }
+    }
 
-        let b = g.get_piece_at(to2.0, to2.1);
+    #[test]
+    fn test_legal_moves_sorted() {
+        let mut g = Game::new();
+        g.make_move((4, 1), (4, 3));
+        g.make_move((3, 6), (3, 4));
+
+        let first = g.legal_moves_sorted();
+        if first != g.legal_moves_sorted() || first != g.clone().legal_moves_sorted() {
+            panic!("The order changed between calls.");
+        }
+        if first.windows(2).any(|w| w[0] >= w[1]) {
+            panic!("The moves are not sorted: {:?}", first);
+        }
+
+        let divide = g.perft_divide(2);
+        if divide.iter().map(|(mv, _)| *mv).collect::<Vec<_>>() != first {
+            panic!("perft_divide should go through the moves in the same order.");
+        }
+        if divide != g.perft_divide(2) {
+            panic!("perft_divide changed between calls.");
+        }
+        if divide.iter().map(|(_, n)| n).sum::<u64>() != g.perft(2) {
+            panic!("The divide counts do not add up to perft.");
+        }
+    }
+
+    #[test]
+    fn test_result_string() {
+        let mut g = Game::new();
+        if g.result_string() != "*" {
+            panic!("Nobody has won yet.");
+        }
+
+        // Fool's mate, black wins.
+        for (from, to) in [
+            ((5, 1), (5, 2)),
+            ((4, 6), (4, 4)),
+            ((6, 1), (6, 3)),
+            ((3, 7), (7, 3)),
+        ] {
+            g.make_move(from, to);
+        }
+        if g.result_string() != "0-1" {
+            panic!("Expected 0-1, got {}", g.result_string());
+        }
+
+        // Scholar's mate, white wins.
+        let mut g = Game::new();
+        for (from, to) in [
+            ((4, 1), (4, 3)),
+            ((4, 6), (4, 4)),
+            ((5, 0), (2, 3)),
+            ((1, 7), (2, 5)),
+            ((3, 0), (7, 4)),
+            ((6, 7), (5, 5)),
+            ((7, 4), (5, 6)),
+        ] {
+            g.make_move(from, to);
+        }
+        if g.result_string() != "1-0" {
+            panic!("Expected 1-0, got {}", g.result_string());
+        }
 
-        if let Some(p) = b {
-            panic!();
+        let stuck = Game::from_placements(
+            &[
+                (0, 7, 'K', Color::Black),
+                (1, 5, 'Q', Color::White),
+                (2, 6, 'K', Color::White),
+            ],
+            Color::Black,
+        )
+        .unwrap();
+        if stuck.result_string() != "1/2-1/2" {
+            panic!("Expected a draw, got {}", stuck.result_string());
         }
     }
 
     #[test]
-    fn test_pawn_move_capture_false() {
+    fn test_pawn_attacks() {
         let g = Game::new();
-        let p = g.get_piece_at(4, 1).unwrap();
-        let m = p.moves[2].prune(&g, (4, 1));
+        let white = g.pawn_attacks(Color::White);
+        if white != (0..8).map(|c| (c, 2)).collect() {
+            panic!(
+                "White's pawns should cover the whole third row, got {:?}",
+                white
+            );
+        }
+        if g.pawn_attacks(Color::Black) != (0..8).map(|c| (c, 5)).collect() {
+            panic!("Black's pawns should cover the whole sixth row.");
+        }
 
-        if m.len() != 0 {
-            panic!();
+        // A pawn on the edge only attacks one square, and sideways pawns attack sideways.
+        let mut g = Game::from_placements(
+            &[
+                (0, 3, 'p', Color::White),
+                (7, 0, 'K', Color::White),
+                (7, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        g.put(4, 4, Some(Piece::new_pawn_facing(Color::Black, (1, 0))));
+        if g.pawn_attacks(Color::White) != HashSet::from([(1, 4)]) {
+            panic!("The edge pawn only attacks b5.");
+        }
+        if g.pawn_attacks(Color::Black) != HashSet::from([(5, 5), (5, 3)]) {
+            panic!("The sideways pawn attacks to the right.");
         }
     }
 
     #[test]
-    fn test_pawn_move_capture_true() {
-        let mut template = test_template;
-        let start = (3 as u8, 3 as u8);
-        let goal = (4 as u8, 4 as u8);
-
-        template[(start.0 + start.1 * 8) as usize] = 'p';
-        template[(goal.0 + goal.1 * 8) as usize] = 'p';
-
-        let b = Game::make_board(template, color_template).ok().unwrap();
-
-        let mut g = Game {
-            board: b,
-            turn_owner: Color::White,
-            turn_count: 1,
-            game_state: GameState::Running,
+    fn test_promote_to_other_color() {
+        let mut g = Game::from_placements(
+            &[
+                (0, 6, 'p', Color::White),
+                (4, 0, 'K', Color::White),
+                (7, 4, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        let traitor = Piece {
+            promotion: Some(PromotionRule {
+                squares: None,
+                ranks: vec!['Q'],
+                color: Some(Color::Black),
+            }),
+            ..Piece::new_pawn(Color::White)
         };
+        g.put(0, 6, Some(traitor));
 
-        g.print_moves(start.0, start.1);
-
-        let p = g.get_piece_at(start.0, start.1).unwrap();
-        let m = p.moves[2].prune(&g, start);
+        g.make_move((0, 6), (0, 7));
+        if !g.promote((0, 7), 'Q') {
+            panic!("The pawn should be allowed to promote.");
+        }
+        match g.get_piece_at(0, 7) {
+            Some(p) if p.rank == 'Q' && p.color == Color::Black && p.promoted => {}
+            p => panic!(
+                "Expected a black queen, got {:?}",
+                p.map(|p| (p.rank, p.color))
+            ),
+        }
+        if g.get_turn_owner() != Color::Black {
+            panic!("It is still black's turn after the promotion.");
+        }
 
-        if m.len() != 1 || !m.contains_key(&(goal.0 + goal.1 * 8)) {
-            panic!();
+        // Everything else still promotes into its own color.
+        let mut g = Game::from_placements(
+            &[
+                (0, 6, 'p', Color::White),
+                (4, 0, 'K', Color::White),
+                (7, 4, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        g.make_move((0, 6), (0, 7));
+        g.promote((0, 7), 'Q');
+        if g.get_piece_at(0, 7).map(|p| p.color) != Some(Color::White) {
+            panic!("A normal pawn should stay white.");
         }
+    }
 
-        println!("Move success: {}", g.make_move(start, goal));
-        let a = g.get_piece_at(goal.0, goal.1);
+    #[test]
+    fn test_make_move_san() {
+        let mut g = Game::new();
+        for san in ["e4", "e5", "Nf3", "Nc6"] {
+            if let Err(e) = g.make_move_san(san) {
+                panic!("{} failed: {}", san, e);
+            }
+        }
 
-        println!("Debug Piece: {:#?}", a);
-        g.print_board();
+        let mut expected = Game::new();
+        for (from, to) in [
+            ((4, 1), (4, 3)),
+            ((4, 6), (4, 4)),
+            ((6, 0), (5, 2)),
+            ((1, 7), (2, 5)),
+        ] {
+            expected.make_move(from, to);
+        }
+        if g.to_char_array() != expected.to_char_array() || g.zobrist() != expected.zobrist() {
+            panic!("The SAN moves ended up somewhere else.");
+        }
 
-        if let Some(p) = a {
-            if p.color == Color::White && p.last_moved == Some(1) && p.times_moved == 1 {
-                return;
+        let before = g.zobrist();
+        for san in ["Nd4d5", "Ke3", "hello"] {
+            if g.make_move_san(san) != Err(MoveError::UnknownSan) {
+                panic!("{} should not be a move.", san);
             }
         }
-        panic!();
+        if g.zobrist() != before {
+            panic!("Failed moves should not change anything.");
+        }
+
+        // The promotion comes from the SAN.
+        let mut g = Game::from_placements(
+            &[
+                (0, 6, 'p', Color::White),
+                (4, 0, 'K', Color::White),
+                (7, 4, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        g.make_move_san("a8=N").unwrap();
+        if g.get_piece_at(0, 7).map(|p| p.rank) != Some('N') || g.get_turn_owner() != Color::Black {
+            panic!("The pawn should have become a knight.");
+        }
     }
 
     #[test]
-    fn test_pawn_move_en_passant() {
-        let mut template = test_template;
-        let start = (3 as u8, 3 as u8);
-        let subgoal = (3 as u8, 4 as u8);
-        let goal = (4 as u8, 5 as u8);
-        let start2 = (4 as u8, 6 as u8);
-        let goal2 = (4 as u8, 4 as u8);
-
-        template[(start.0 + start.1 * 8) as usize] = 'p';
-        template[(start2.0 + start2.1 * 8) as usize] = 'p';
-
-        let b = Game::make_board(template, color_template).ok().unwrap();
-
-        let mut g = Game {
-            board: b,
-            turn_owner: Color::White,
-            turn_count: 1,
-            game_state: GameState::Running,
+    fn test_make_rolls_back_bad_promotions() {
+        let mut g = Game::from_placements(
+            &[
+                (0, 6, 'p', Color::White),
+                (4, 0, 'K', Color::White),
+                (7, 4, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        // This pawn drags a knight-only pawn onto the last row with it.
+        let mut dragger = Piece::new_pawn(Color::White);
+        dragger.moves[0].effect.push(Effect::Move(
+            Position::Relative((1, 0)),
+            Position::Relative((1, 1)),
+        ));
+        g.put(0, 6, Some(dragger));
+        g.put(
+            1,
+            6,
+            Some(Piece {
+                promotion: Some(PromotionRule {
+                    squares: None,
+                    ranks: vec!['N'],
+                    color: None,
+                }),
+                ..Piece::new_pawn(Color::White)
+            }),
+        );
+        let before = g.to_char_array();
+
+        let mv = ChessMove {
+            from: (0, 6),
+            to: (0, 7),
+            promotion: Some('Q'),
         };
+        if g.make(mv) != Err(MoveError::InvalidPromotion) {
+            panic!("The knight-only pawn can not become a queen.");
+        }
+        if g.to_char_array() != before || g.get_game_state() != GameState::Running {
+            panic!("A failed make should not change anything.");
+        }
 
-        println!("Move part 1 success: {}", g.make_move(start, subgoal));
-
-        println!("Move part 2 success: {}", g.make_move(start2, goal2));
-
-        println!("Move part 3 success: {}", g.make_move(subgoal, goal));
-
-        let a = g.get_piece_at(goal.0, goal.1);
-        let b = g.get_piece_at(goal2.0, goal2.1);
-
-        println!("Debug Piece: {:#?}", a);
-        g.print_board();
+        // The lookahead helpers give up instead of looping forever.
+        if g.gives_check((0, 6), (0, 7))
+            || g.is_threefold_after((0, 6), (0, 7))
+            || g.square_attacked_after((0, 6), (0, 7), (0, 7), Color::White)
+                .is_some()
+        {
+            panic!("Nothing can be said about a move that can not be finished.");
+        }
 
-        if let Some(p) = a {
-            if let Some(_p) = b {
-                panic!()
-            }
-            if p.color == Color::White && p.last_moved == Some(2) && p.times_moved == 2 {
-                return;
+        // Same thing for random games, which now stop and wait for the promotion instead.
+        #[cfg(feature = "random")]
+        {
+            use rand::{rngs::StdRng, SeedableRng};
+            let stuck = (0..20)
+                .filter(|&seed| {
+                    let mut h = g.clone();
+                    h.play_random_game(&mut StdRng::seed_from_u64(seed), 1) == GameState::Promote
+                })
+                .count();
+            if stuck == 0 {
+                panic!("Some random game should have tried the pawn move.");
             }
         }
-        panic!();
     }
 
     #[test]
-    fn test_pawn_move_capture_true_flip() {
-        let mut template = test_template;
-        let start = (4 as u8, 3 as u8);
-        let goal = (3 as u8, 4 as u8);
-
-        template[(start.0 + start.1 * 8) as usize] = 'p';
-        template[(goal.0 + goal.1 * 8) as usize] = 'p';
-
-        let b = Game::make_board(template, color_template).ok().unwrap();
-
-        let mut g = Game {
-            board: b,
-            turn_owner: Color::White,
-            turn_count: 1,
-            game_state: GameState::Running,
+    fn test_threefold_rights() {
+        #[allow(clippy::type_complexity)]
+        let play = |g: &mut Game, moves: &[((u8, u8), (u8, u8))]| {
+            for &(from, to) in moves {
+                if !g.make_move(from, to) {
+                    panic!("Could not move {:?} to {:?}", from, to);
+                }
+            }
         };
+        let knights = [
+            ((6, 0), (5, 2)),
+            ((6, 7), (7, 5)),
+            ((5, 2), (6, 0)),
+            ((7, 5), (6, 7)),
+        ];
 
-        g.print_moves(start.0, start.1);
-
-        let p = g.get_piece_at(start.0, start.1).unwrap();
-        let m = p.moves[2].prune(&g, start);
-
-        if m.len() != 1 || !m.contains_key(&(goal.0 + goal.1 * 8)) {
-            panic!();
+        // The rooks walk away and back, so the start position comes back without castling rights.
+        let mut g = Game::new();
+        play(
+            &mut g,
+            &[
+                ((6, 0), (5, 2)),
+                ((6, 7), (5, 5)),
+                ((7, 0), (6, 0)),
+                ((7, 7), (6, 7)),
+                ((6, 0), (7, 0)),
+                ((6, 7), (7, 7)),
+                ((5, 2), (6, 0)),
+                ((5, 5), (6, 7)),
+            ],
+        );
+        play(&mut g, &knights);
+        if g.is_threefold_now() {
+            panic!("The first time had castling rights, so this is only the second time.");
+        }
+        play(&mut g, &knights);
+        if !g.is_threefold_now() {
+            panic!("This is the third time without castling rights.");
         }
 
-        println!("Move success: {}", g.make_move(start, goal));
-        let a = g.get_piece_at(goal.0, goal.1);
-
-        println!("Debug Piece: {:#?}", a);
-        g.print_board();
-
-        if let Some(p) = a {
-            if p.color == Color::White && p.last_moved == Some(1) && p.times_moved == 1 {
-                return;
-            }
+        // Same thing with en passant, which can only be done right after d5.
+        let mut g = Game::new();
+        play(
+            &mut g,
+            &[
+                ((4, 1), (4, 3)),
+                ((0, 6), (0, 5)),
+                ((4, 3), (4, 4)),
+                ((3, 6), (3, 4)),
+            ],
+        );
+        play(&mut g, &knights);
+        play(&mut g, &knights);
+        if g.is_threefold_now() {
+            panic!("The first time en passant was possible, so this is only the second time.");
+        }
+        play(&mut g, &knights);
+        if !g.is_threefold_now() {
+            panic!("This is the third time without en passant.");
         }
-        panic!();
     }
 
     #[test]
-    fn test_pawn_black_move_normal() {
-        let g = Game::new();
-        let p = g.get_piece_at(4, 6).unwrap();
-        let m = p.moves[0].prune(&g, (4, 6));
+    fn test_transposition_table_lines() {
+        // The rook on b1 walks up to b7, then the other one mates on a8.
+        let g = Game::from_placements(
+            &[
+                (0, 0, 'K', Color::White),
+                (1, 0, 'R', Color::White),
+                (0, 5, 'R', Color::White),
+                (7, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        let mut tt = TranspositionTable::new(1 << 16);
+        for depth in [4, 3] {
+            let result = g.analyze_tt(depth, &mut tt);
+            if result.score != Score::Mate(2) {
+                panic!(
+                    "Depth {} should find mate in 2, got {:?}",
+                    depth, result.score
+                );
+            }
+        }
 
-        g.print_moves(4, 6);
+        // A position seen before should not cut the line short.
+        let g = Game::new();
+        let mut tt = TranspositionTable::new(1 << 16);
+        for _ in 0..2 {
+            let result = g.analyze_tt(3, &mut tt);
+            if result.pv.len() != 3 {
+                panic!("Expected 3 moves in the line, got {:?}", result.pv);
+            }
+        }
 
-        if m.len() != 1 || !m.contains_key(&(4 + 5 * 8)) {
-            panic!();
+        // En passant only counts while it can actually be done.
+        let mut g = Game::new();
+        for (from, to) in [
+            ((4, 1), (4, 3)),
+            ((0, 6), (0, 5)),
+            ((4, 3), (4, 4)),
+            ((3, 6), (3, 4)),
+        ] {
+            g.make_move(from, to);
+        }
+        let with_passant = g.zobrist();
+        for (from, to) in [
+            ((6, 0), (5, 2)),
+            ((6, 7), (7, 5)),
+            ((5, 2), (6, 0)),
+            ((7, 5), (6, 7)),
+        ] {
+            g.make_move(from, to);
+        }
+        let without = g.zobrist();
+        for (from, to) in [
+            ((6, 0), (5, 2)),
+            ((6, 7), (7, 5)),
+            ((5, 2), (6, 0)),
+            ((7, 5), (6, 7)),
+        ] {
+            g.make_move(from, to);
+        }
+        if with_passant == without || without != g.zobrist() {
+            panic!("Only the first position could be taken en passant.");
         }
     }
 
     #[test]
-    fn test_pawn_black_move_double() {
-        let g = Game::new();
-        let p = g.get_piece_at(4, 6).unwrap();
-        let m = p.moves[1].prune(&g, (4, 6));
-
-        g.print_moves(4, 6);
-
-        if m.len() != 1 || !m.contains_key(&(4 + 4 * 8)) {
-            panic!();
+    fn test_castling_only_from_home_row() {
+        // Both kings and the h rooks have never moved, but white's are on the fourth row.
+        let g = Game::from_placements(
+            &[
+                (4, 3, 'K', Color::White),
+                (7, 3, 'R', Color::White),
+                (4, 7, 'K', Color::Black),
+                (7, 7, 'R', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        if g.can_castle(Color::White) != (false, false) || g.clone().make_move((4, 3), (6, 3)) {
+            panic!("White should not be able to castle away from its first row.");
+        }
+        if g.can_castle(Color::Black) != (true, false) {
+            panic!("Black is on its home row, so it can castle king side.");
         }
     }
 
     #[test]
-    fn test_pawn_black_move_capture_false() {
-        let g = Game::new();
-        let p = g.get_piece_at(4, 6).unwrap();
-        let m = p.moves[2].prune(&g, (4, 6));
-
-        g.print_moves(4, 6);
-
-        if m.len() != 0 {
-            panic!();
+    fn test_cannon_check_evasions() {
+        // The white king is boxed in, and the knight on e3 is the only screen between it and the cannon.
+        let mut g = Game::from_placements(
+            &[
+                (4, 0, 'K', Color::White),
+                (3, 0, 'R', Color::White),
+                (5, 0, 'R', Color::White),
+                (3, 1, 'p', Color::White),
+                (5, 1, 'p', Color::White),
+                (4, 2, 'N', Color::White),
+                (0, 7, 'K', Color::Black),
+            ],
+            Color::White,
+        )
+        .unwrap();
+        let mut cannon = Piece::new(Color::Black, 'R');
+        cannon.rank = 'C';
+        let mut capture = cannon.moves[0].clone();
+        capture.screens = Some(1);
+        cannon.moves[0].can_capture = false;
+        cannon.moves.push(capture);
+        g.put(4, 7, Some(cannon));
+        g.compute_state();
+
+        // Moving the knight anywhere but onto its own rooks takes the screen away.
+        if g.legal_moves_from(4, 2).len() != 6 || g.get_game_state() != GameState::Check {
+            panic!(
+                "The knight should be able to move, got {:?} in {:?}",
+                g.legal_moves_from(4, 2),
+                g.get_game_state()
+            );
+        }
+        let mut moves = g.legal_moves();
+        moves.sort();
+        if moves != brute_force_moves(&g) {
+            panic!("Got {:?}, expected {:?}", moves, brute_force_moves(&g));
         }
     }
 
     #[test]
-    fn test_pawn_black_move_capture_true() {
-        let mut template = test_template;
-        template[3 + 3 * 8] = 'p';
-        template[4 + 4 * 8] = 'p';
-        let b = Game::make_board(template, color_template).ok().unwrap();
-        let g = Game {
-            board: b,
-            turn_owner: Color::White,
-            turn_count: 0,
-            game_state: GameState::Running,
-        };
-
-        g.print_moves(4, 4);
-
-        let p = g.get_piece_at(4, 4).unwrap();
-        let m = p.moves[2].prune(&g, (4, 4));
-
-        if m.len() != 1 || !m.contains_key(&(3 + 3 * 8)) {
-            panic!();
+    fn test_mirror_keeps_custom_pieces() {
+        let mut g = Game::from_placements(
+            &[(4, 0, 'K', Color::White), (4, 7, 'K', Color::Black)],
+            Color::White,
+        )
+        .unwrap();
+        // A knight that only jumps one way, and promotes to a rook on d6.
+        let mut jumper = Piece::new(Color::White, 'N');
+        jumper.rank = 'J';
+        jumper.moves[0].directions = vec![(1, 2)];
+        jumper.moves[0].mirror = None;
+        jumper.promotion = Some(PromotionRule {
+            squares: Some(vec![(3, 5)]),
+            ranks: vec!['R'],
+            color: None,
+        });
+        g.put(1, 1, Some(jumper));
+        let mut pawn = Piece::new(Color::White, 'p');
+        pawn.promotion.as_mut().unwrap().ranks = vec!['N'];
+        g.put(6, 1, Some(pawn));
+
+        let m = g.mirror_vertical();
+        let jumper = m.get_piece_at(1, 6).unwrap();
+        if jumper.rank != 'J' || jumper.color != Color::Black || !jumper.promotes_at(3, 2) {
+            panic!("The custom piece did not survive the mirror: {:?}", jumper);
+        }
+        let targets: Vec<_> = m.legal_moves_from(1, 6).iter().map(|cm| cm.to).collect();
+        if targets != vec![(2, 4)] {
+            panic!("The jump should be mirrored, got {:?}", targets);
+        }
+        if m.get_piece_at(6, 6).unwrap().promotion_ranks() != ['N'] {
+            panic!("The pawn forgot what it promotes to.");
+        }
+        if m.mirror_vertical().legal_moves() != g.legal_moves() {
+            panic!("Mirroring twice should give back the same moves.");
         }
     }
 
     #[test]
-    fn test_pawn_black_move_capture_true_flip() {
-        let mut template = test_template;
-        template[4 + 3 * 8] = 'p';
-        template[3 + 4 * 8] = 'p';
-        let b = Game::make_board(template, color_template).ok().unwrap();
-        let g = Game {
-            board: b,
-            turn_owner: Color::White,
-            turn_count: 0,
-            game_state: GameState::Running,
-        };
-
-        g.print_moves(3, 4);
+    fn test_hands_in_position_key() {
+        let g = Game::new();
+        let mut knight = g.clone();
+        knight.hands[0].push('N');
+        if g.position_key() == knight.position_key() || g.zobrist() == knight.zobrist() {
+            panic!("A knight in hand makes it a different position.");
+        }
 
-        let p = g.get_piece_at(3, 4).unwrap();
-        let m = p.moves[2].prune(&g, (3, 4));
+        // Two knights are not the same as one, and the order they came in does not matter.
+        let mut two = knight.clone();
+        two.hands[0].push('N');
+        let mut mixed = two.clone();
+        mixed.hands[0].insert(0, 'p');
+        two.hands[0].push('p');
+        if two.zobrist() == knight.zobrist()
+            || two.position_key() != mixed.position_key()
+            || two.zobrist() != mixed.zobrist()
+        {
+            panic!("Only what is in the hands should count.");
+        }
 
-        if m.len() != 1 || !m.contains_key(&(4 + 3 * 8)) {
-            panic!();
+        // Same pieces, but in the other player's hand.
+        let mut black = g.clone();
+        black.hands[1].push('N');
+        if black.position_key() == knight.position_key() || black.zobrist() == knight.zobrist() {
+            panic!("It matters whose hand the knight is in.");
         }
     }
 
     #[test]
-    fn test_promote() {
+    fn test_clone_without_history() {
         let mut g = Game::new();
-        g.just_move((2, 1), (2, 7));
-        g.just_move((7, 6), (7, 7));
-
-        g.print_board();
-
-        print!("This move should fail: ");
-        if g.make_move((1, 1), (1, 2)) {
-            panic!("The move did not fail.");
+        // The knights go out and back twice, which is the third time for the start position.
+        let dance = [
+            ((6, 0), (5, 2)),
+            ((6, 7), (5, 5)),
+            ((5, 2), (6, 0)),
+            ((5, 5), (6, 7)),
+        ];
+        for (from, to) in dance.iter().chain(dance.iter()) {
+            g.make_move(*from, *to);
         }
-
-        print!("This move should fail: ");
-        if g.make_move((1, 6), (1, 5)) {
-            panic!("The move did not fail.");
+        if !g.is_threefold_now() {
+            panic!("The start position should have come up three times.");
         }
 
-        if let Some((pos, piece)) = g.get_promotion() {
-            if piece.rank != 'p' || piece.color != Color::White || pos != (2, 7) {
-                panic!("Wrong piece or position.");
+        let mut c = g.clone_without_history();
+        if c.history.len() != 1
+            || !c.reversible_history().is_empty()
+            || c.is_threefold_now()
+            || c.to_char_array() != g.to_char_array()
+            || c.zobrist() != g.zobrist()
+        {
+            panic!("The copy should be the same position, without the history.");
+        }
+        for (from, to) in dance {
+            if !c.make_move(from, to) {
+                panic!("The copy should still play moves.");
             }
+        }
+        if c.reversible_history().len() != 4 || c.history.len() != 5 || c.is_threefold_now() {
+            panic!("Only the moves made on the copy should count.");
+        }
+    }
 
-            if g.promote(pos, 'p')
-                || g.promote(pos, 'K')
-                || g.promote((4, 6), 'Q')
-                || g.promote((7, 7), 'Q')
-            {
-                g.print_board();
-                panic!("It should not have promoted!");
-            }
+    #[test]
+    fn test_validate_pawn_rows() {
+        let mut t = TEST_TEMPLATE;
+        t[3 + 7 * 8] = 'p';
 
-            if !g.promote(pos, 'Q') {
-                panic!("It should have promoted, but did not.");
-            }
+        if Game::make_board_validated(t, COLOR_TEMPLATE, true).is_ok() {
+            panic!("A pawn on the last row should not be allowed.");
+        }
 
-            if let Some(p) = g.get_piece_at(pos.0, pos.1) {
-                if p.rank != 'Q' {
-                    panic!("It lied about promoting!");
-                }
-                println!("Success!");
-            } else {
-                panic!("It is just gone. What?");
-            }
-        } else {
-            panic!("No piece to promote.");
+        // The default should still be lenient
+        if Game::make_board(t, COLOR_TEMPLATE).is_err() {
+            panic!("make_board should not validate pawns.");
+        }
+    }
+
+    #[test]
+    fn test_all_requirements_checked() {
+        let g = Game::new();
+        let mut m = g.get_piece_at(4, 1).unwrap().moves[1].clone();
+        if m.prune(&g, (4, 1)).is_empty() {
+            panic!("The double step should work from the start.");
+        }
+
+        // The first requirement still holds, so only this one can stop it.
+        let mut queen = m.requirements[0];
+        queen.rank = Some('Q');
+        m.requirements.push(queen);
+        if !m.prune(&g, (4, 1)).is_empty() {
+            panic!("Every requirement has to hold, not just the first one.");
         }
     }
 
     #[test]
     fn test_check() {
-        let mut t = test_template;
+        let mut t = TEST_TEMPLATE;
         t[3 + 3 * 8] = 'R';
-        let b = Game::make_board(t, color_template).unwrap();
+        let b = Game::make_board(t, COLOR_TEMPLATE).unwrap();
         let mut g = Game {
             board: b,
             turn_owner: Color::White,
             turn_count: 1,
             game_state: GameState::Running,
+            ..Default::default()
         };
 
         g.make_move((3, 3), (6, 3));
@@ -924,4 +5738,61 @@ mod tests {
             panic!();
         }
     }
+
+    #[test]
+    fn test_castling_rules() {
+        let mut g = Game::new();
+        // 1. e4 e5 2. Nf3 Nc6 3. Bc4 Nf6 4. d3 Bb4+
+        for (from, to) in [
+            ((4, 1), (4, 3)),
+            ((4, 6), (4, 4)),
+            ((6, 0), (5, 2)),
+            ((1, 7), (2, 5)),
+            ((5, 0), (2, 3)),
+            ((6, 7), (5, 5)),
+            ((3, 1), (3, 2)),
+            ((5, 7), (1, 3)),
+        ] {
+            if !g.make_move(from, to) {
+                panic!("Could not move {:?} to {:?}", from, to);
+            }
+        }
+        if g.make_move((4, 0), (6, 0)) {
+            panic!("White castled out of check.");
+        }
+
+        // Once the check is blocked, both sides can castle, black included.
+        g.make_move((2, 1), (2, 2));
+        if !g.make_move((4, 7), (6, 7)) {
+            panic!("Black should be able to castle.");
+        }
+        if !g.make_move((4, 0), (6, 0)) {
+            panic!("White should be able to castle now.");
+        }
+        if g.get_piece_at(5, 0).map(|p| p.rank) != Some('R')
+            || g.get_piece_at(5, 7).map(|p| (p.rank, p.color)) != Some(('R', Color::Black))
+        {
+            panic!("The rooks should have jumped over the kings.");
+        }
+
+        // 1. g3 b6 2. Bh3 Ba6 3. Nf3 Nc6 4. e3 Nf6, and the bishop on a6 covers f1.
+        let mut g = Game::new();
+        for (from, to) in [
+            ((6, 1), (6, 2)),
+            ((1, 6), (1, 5)),
+            ((5, 0), (7, 2)),
+            ((2, 7), (0, 5)),
+            ((6, 0), (5, 2)),
+            ((1, 7), (2, 5)),
+            ((4, 1), (4, 2)),
+            ((6, 7), (5, 5)),
+        ] {
+            if !g.make_move(from, to) {
+                panic!("Could not move {:?} to {:?}", from, to);
+            }
+        }
+        if g.make_move((4, 0), (6, 0)) {
+            panic!("White castled through check.");
+        }
+    }
 }
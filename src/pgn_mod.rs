@@ -0,0 +1,275 @@
+use super::*;
+use std::io::{BufRead, Lines};
+
+/// One game read from a PGN file.
+#[derive(Clone)]
+pub struct GameRecord {
+    /// The tags, like ("White", "Magnus"), in the order they were written.
+    pub headers: Vec<(String, String)>,
+    /// Every move of the game, in order.
+    pub moves: Vec<ChessMove>,
+    /// How the movetext ended: "1-0", "0-1", "1/2-1/2" or "*". None if it just stopped.
+    pub result: Option<String>,
+    /// The game after all the moves have been made.
+    pub game: Game,
+}
+
+impl GameRecord {
+    /// The value of the tag with that name, if there is one.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Reads games from a PGN file one at a time, so the whole file never has to be in memory.\
+/// Every game is played out from the normal start position, so FEN tags are not supported.
+pub struct PgnReader<R: BufRead> {
+    lines: Lines<R>,
+    /// A tag line that turned out to belong to the next game.
+    pending: Option<String>,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> PgnReader<R> {
+        PgnReader {
+            lines: reader.lines(),
+            pending: None,
+        }
+    }
+
+    fn next_line(&mut self) -> Option<Result<String, String>> {
+        if let Some(line) = self.pending.take() {
+            return Some(Ok(line));
+        }
+        self.lines
+            .next()
+            .map(|l| l.map_err(|e| format!("Could not read the PGN: {}", e)))
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<GameRecord, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut headers = Vec::new();
+        let mut has_movetext = false;
+        let mut tokens = Tokenizer::default();
+
+        while let Some(line) = self.next_line() {
+            let line = match line {
+                Ok(l) => l,
+                Err(e) => return Some(Err(e)),
+            };
+            let trimmed = line.trim();
+
+            if trimmed.starts_with('[') {
+                if has_movetext {
+                    // The next game has started, without the last one having a result.
+                    self.pending = Some(line);
+                    break;
+                }
+                match parse_tag(trimmed) {
+                    Some(tag) => headers.push(tag),
+                    None => return Some(Err(format!("Broken tag: {}", trimmed))),
+                }
+            } else if !trimmed.is_empty() {
+                has_movetext = true;
+                tokens.feed(trimmed);
+                if tokens.all.last().is_some_and(|t| is_result(t)) {
+                    break;
+                }
+            }
+        }
+
+        if headers.is_empty() && !has_movetext {
+            return None;
+        }
+        Some(play(headers, tokens.all))
+    }
+}
+
+/// Turns `[Name "Value"]` into ("Name", "Value").
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((name.to_owned(), value.replace("\\\"", "\"")))
+}
+
+fn is_result(token: &str) -> bool {
+    matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*")
+}
+
+/// Splits the movetext into moves and results, without comments, variations, move numbers or NAGs.\
+/// It gets the movetext one line at a time, since ; comments only go to the end of the line.
+#[derive(Default)]
+struct Tokenizer {
+    all: Vec<String>,
+    current: String,
+    comment: bool,
+    variation: u32,
+}
+
+impl Tokenizer {
+    fn feed(&mut self, line: &str) {
+        for c in line.chars() {
+            match c {
+                '{' if !self.comment => self.comment = true,
+                '}' if self.comment => self.comment = false,
+                _ if self.comment => {}
+                ';' => break,
+                '(' => self.variation += 1,
+                ')' if self.variation > 0 => self.variation -= 1,
+                _ if self.variation > 0 => {}
+                c if c.is_whitespace() => self.end_token(),
+                c => self.current.push(c),
+            }
+        }
+        self.end_token();
+    }
+
+    fn end_token(&mut self) {
+        let t = std::mem::take(&mut self.current);
+        // "12." and "12..." are move numbers, but "12.e4" has the move stuck to it.
+        let t = match t.find('.') {
+            Some(i) if t[..i].chars().all(|c| c.is_ascii_digit()) => t
+                .trim_start_matches(|c: char| c.is_ascii_digit() || c == '.')
+                .to_owned(),
+            _ => t,
+        };
+        if !t.is_empty() && !t.starts_with('$') {
+            self.all.push(t);
+        }
+    }
+}
+
+fn play(headers: Vec<(String, String)>, tokens: Vec<String>) -> Result<GameRecord, String> {
+    if headers.iter().any(|(n, _)| n == "FEN") {
+        return Err("Games starting from a FEN are not supported".to_owned());
+    }
+
+    let mut game = Game::new();
+    let mut moves = Vec::new();
+    let mut result = None;
+    for token in tokens {
+        if is_result(&token) {
+            result = Some(token);
+            break;
+        }
+        let mv = game.parse_san(&token)?;
+        game.make(mv)
+            .map_err(|e| format!("Move {} ({}) failed: {:?}", moves.len() + 1, token, e))?;
+        moves.push(mv);
+    }
+
+    Ok(GameRecord {
+        headers,
+        moves,
+        result,
+        game,
+    })
+}
+
+impl Game {
+    /// The game state as the value of a PGN `Result` tag: "1-0", "0-1", "1/2-1/2" or "*" if it is not over.\
+    /// Draws that have to be claimed do not count until someone actually ends the game.
+    pub fn result_string(&self) -> &'static str {
+        // The turn owner is the one who lost in all of these, except when being stuck wins.
+        let winner = match self.game_state {
+            GameState::Running | GameState::Promote | GameState::Check => return "*",
+            GameState::Stalemate => return "1/2-1/2",
+            GameState::CheckMate | GameState::CrucialCaptured => self.turn_owner.opposite(),
+            GameState::StalemateWin => self.turn_owner,
+        };
+        match winner {
+            Color::White => "1-0",
+            Color::Black => "0-1",
+        }
+    }
+
+    /// Reads the SAN string with `parse_san` and makes the move, promotion and all.\
+    /// Nothing is changed if it fails.
+    pub fn make_move_san(&mut self, san: &str) -> Result<(), MoveError> {
+        if !matches!(self.game_state, GameState::Running | GameState::Check) {
+            return Err(MoveError::WrongState);
+        }
+        let mv = self.parse_san(san).map_err(|_| MoveError::UnknownSan)?;
+        self.make(mv)
+    }
+
+    /// Figures out which legal move a SAN string like "Nbd7", "exd5", "e8=Q+" or "O-O" means.\
+    /// Fails if no legal move matches, or if more than one does.
+    pub fn parse_san(&self, san: &str) -> Result<ChessMove, String> {
+        let clean = san.trim_end_matches(['+', '#', '!', '?']);
+        let moves = self.legal_chess_moves();
+
+        let castling = match clean {
+            "O-O" | "0-0" => Some("O-O"),
+            "O-O-O" | "0-0-0" => Some("O-O-O"),
+            _ => None,
+        };
+        if let Some(command) = castling {
+            return moves
+                .into_iter()
+                .find(|m| self.move_command(m.from, m.to).as_deref() == Some(command))
+                .ok_or(format!("{} can not be made", san));
+        }
+
+        let (clean, promotion) = match clean.split_once('=') {
+            Some((rest, rank)) => (rest, rank.chars().next()),
+            None => match clean.chars().last() {
+                Some(c) if "QRBN".contains(c) && clean.len() > 2 => {
+                    (&clean[..clean.len() - 1], Some(c))
+                }
+                _ => (clean, None),
+            },
+        };
+
+        let mut chars: Vec<char> = clean.chars().filter(|&c| c != 'x').collect();
+        let rank = match chars.first() {
+            Some(&c) if "KQRBN".contains(c) => {
+                chars.remove(0);
+                c
+            }
+            _ => 'p',
+        };
+        if chars.len() < 2 {
+            return Err(format!("{} is not a move", san));
+        }
+        let to = square(chars[chars.len() - 2], chars[chars.len() - 1])
+            .ok_or(format!("{} has no target square", san))?;
+        // Whatever is left tells pieces of the same rank apart.
+        let hints = &chars[..chars.len() - 2];
+
+        let matching: Vec<ChessMove> = moves
+            .into_iter()
+            .filter(|m| {
+                m.to == to
+                    && m.promotion == promotion
+                    && self.get_piece_at(m.from.0, m.from.1).map(|p| p.rank) == Some(rank)
+                    && hints.iter().all(|&h| match h {
+                        'a'..='h' => m.from.0 == h as u8 - b'a',
+                        '1'..='8' => m.from.1 == h as u8 - b'1',
+                        _ => false,
+                    })
+            })
+            .collect();
+
+        match matching[..] {
+            [mv] => Ok(mv),
+            [] => Err(format!("{} can not be made", san)),
+            _ => Err(format!("{} could be more than one move", san)),
+        }
+    }
+}
+
+/// Turns 'e' and '4' into (4, 3).
+fn square(file: char, rank: char) -> Option<(u8, u8)> {
+    if !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    Some((file as u8 - b'a', rank as u8 - b'1'))
+}
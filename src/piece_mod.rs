@@ -11,13 +11,39 @@ pub struct Piece {
     pub rank: char,
     pub color: Color,
     pub is_crucial: bool,
-    pub can_promote: bool,
+    /// None if the piece never promotes.
+    pub promotion: Option<PromotionRule>,
     pub last_moved: Option<u32>,
     pub times_moved: u32,
+    /// How many squares the piece went on its last move, counting diagonal steps as one.
+    pub last_distance: u8,
+    /// True if the piece started out as something else and got promoted.
+    pub promoted: bool,
     pub moves: Vec<Move>,
+    /// The direction the piece is walking towards. Pawns move and promote this way.
+    pub forward: (i8, i8),
 }
 
+/// Describes where a piece gets promoted, and what it is allowed to become.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PromotionRule {
+    /// The positions where the piece gets promoted.
+    /// None means the far end of the board, seen from the piece's forward direction.
+    pub squares: Option<Vec<(u8, u8)>>,
+    /// The ranks it can promote to.
+    pub ranks: Vec<char>,
+    /// The color it becomes when it promotes. None means it stays its own color,
+    /// which is what you want unless you are playing something weird.
+    pub color: Option<Color>,
+}
 
+/// White walks up the board, black walks down.
+pub fn default_forward(color: Color) -> (i8, i8) {
+    match color {
+        Color::White => (0, 1),
+        Color::Black => (0, -1),
+    }
+}
 
 pub fn position(pos: Position, rel: (u8, u8)) -> (u8, u8) {
     match pos {
@@ -44,6 +70,19 @@ impl Piece {
         }
     }
 
+    /// How much the piece is worth, in pawns times 100.\
+    /// Kings are worth nothing, since they can not be captured anyway.
+    pub fn value(&self) -> i32 {
+        match self.rank {
+            'p' => 100,
+            'N' => 300,
+            'B' => 300,
+            'R' => 500,
+            'Q' => 900,
+            _ => 0,
+        }
+    }
+
     pub fn get_all_possible_moves(
         &self,
         col: u8,
@@ -51,10 +90,21 @@ impl Piece {
         game: &Game,
     ) -> HashMap<u8, Vec<Effect>> {
         let mut all = HashMap::<u8, Vec<Effect>>::new();
+        let evasions = game.evasion_mask(self);
+        let skip_safety = game.can_skip_safety(self, col, row);
         for m in &self.moves {
             let batch = m.prune(game, (col, row));
             for (key, val) in batch {
-                if !game.is_safe_move((col, row), (key % 8, key >> 3), &val, self.color) {
+                // Moves with effects might capture the checker somewhere else, so they get the slow check.
+                if let Some(mask) = evasions {
+                    if mask & (1 << key) == 0 && val.is_empty() {
+                        continue;
+                    }
+                }
+                // Same thing here, en passant can take away two pieces in front of the king.
+                let safe = (skip_safety && val.is_empty())
+                    || game.is_safe_move((col, row), unpack_square(key), &val, self.color);
+                if !safe {
                     continue;
                 }
 
@@ -65,18 +115,31 @@ impl Piece {
         all
     }
 
+    /// The same moves as `get_all_possible_moves`, but each destination comes with the index of
+    /// the entry in `moves` that made it. Good for finding out why a custom piece can go somewhere weird.\
+    /// If several entries reach the same square, all of them are listed. Sorted by square.
+    pub fn moves_with_source(&self, col: u8, row: u8, game: &Game) -> Vec<(u8, usize)> {
+        let mut all = Vec::new();
+        for (i, m) in self.moves.iter().enumerate() {
+            for (key, val) in m.prune(game, (col, row)) {
+                if game.is_safe_move((col, row), unpack_square(key), &val, self.color) {
+                    all.push((key, i));
+                }
+            }
+        }
+        all.sort();
+        all
+    }
+
     pub fn get_danger_zone(&self, col: u8, row: u8, game: &Game) -> HashSet<u8> {
         let mut all = HashSet::<u8>::new();
         for m in &self.moves {
             if !m.can_capture {
                 let mut capture_effect = false;
                 for e in &m.effect {
-                    match e {
-                        Effect::Capture(_a) => {
-                            capture_effect = true;
-                            break;
-                        }
-                        _ => {}
+                    if let Effect::Capture(_a) = e {
+                        capture_effect = true;
+                        break;
                     }
                 }
                 if !capture_effect {
@@ -89,17 +152,11 @@ impl Piece {
                 if m.can_capture {
                     all.insert(key);
                 }
-                for e in &m.effect {
-                    match e {
-                        Effect::Capture(p) => match p {
-                            Position::Global(g) => {
-                                all.insert(g.0 + g.1 * 8);
-                            }
-                            Position::Relative(r) => {
-                                all.insert(r.0 as u8 + col + (r.1 as u8 + row) * 8);
-                            }
-                        },
-                        _ => {}
+                // Use the effects from the batch, since they have been mirrored properly.
+                for e in &val {
+                    if let Effect::Capture(p) = e {
+                        let (c, r) = position(*p, (col, row));
+                        all.insert(pack_square(c, r));
                     }
                 }
             }
@@ -107,22 +164,107 @@ impl Piece {
         all
     }
 
+    /// Checks if the piece would need to be promoted when standing at the position.
+    /// Unless its promotion rule says otherwise, that is when it can not go any further forward.
+    pub fn promotes_at(&self, col: u8, row: u8) -> bool {
+        match &self.promotion {
+            None => false,
+            Some(PromotionRule {
+                squares: Some(squares),
+                ..
+            }) => squares.contains(&(col, row)),
+            Some(PromotionRule { squares: None, .. }) => {
+                let c = col as i8 + self.forward.0;
+                let r = row as i8 + self.forward.1;
+                !(0..BOARD_SIZE as i8).contains(&c) || !(0..BOARD_SIZE as i8).contains(&r)
+            }
+        }
+    }
+
+    /// The ranks the piece can promote to. Empty if it can not promote at all.
+    pub fn promotion_ranks(&self) -> &[char] {
+        match &self.promotion {
+            Some(rule) => &rule.ranks,
+            None => &[],
+        }
+    }
+
+    /// The same piece, but upside down and with the other color.\
+    /// Everything that depends on the rows (moves, requirements, promotion squares) gets flipped,
+    /// so custom pieces keep working the same way on the other side of the board.
+    pub fn mirrored(&self) -> Piece {
+        let flip_row = |row: u8| BOARD_SIZE - 1 - row;
+        let flip_pos = |pos: Position| match pos {
+            Position::Relative((c, r)) => Position::Relative((c, -r)),
+            Position::Global((c, r)) => Position::Global((c, flip_row(r))),
+        };
+
+        let mut piece = self.clone();
+        piece.color = self.color.opposite();
+        piece.forward = (self.forward.0, -self.forward.1);
+        if let Some(rule) = &mut piece.promotion {
+            if let Some(squares) = &mut rule.squares {
+                for square in squares.iter_mut() {
+                    square.1 = flip_row(square.1);
+                }
+            }
+            rule.color = rule.color.map(|c| c.opposite());
+        }
+        for m in &mut piece.moves {
+            m.color = m.color.opposite();
+            for dir in &mut m.directions {
+                dir.1 = -dir.1;
+            }
+            for req in &mut m.requirements {
+                req.board_pos.1 = req.board_pos.1.map(flip_row);
+                req.relative_pos = req.relative_pos.map(|(c, r)| (c, -r));
+                req.color = req.color.map(|c| c.opposite());
+            }
+            for effect in &mut m.effect {
+                *effect = match *effect {
+                    Effect::Capture(pos) => Effect::Capture(flip_pos(pos)),
+                    Effect::Move(from, to) => Effect::Move(flip_pos(from), flip_pos(to)),
+                };
+            }
+        }
+        piece
+    }
+
     pub fn new_pawn(color: Color) -> Piece {
+        Piece::new_pawn_facing(color, default_forward(color))
+    }
+
+    /// Makes a pawn that walks in the `forward` direction, rather than the one its color gives it.\
+    /// `forward` should be a single straight step, like (1, 0) for a pawn that walks to the right.
+    pub fn new_pawn_facing(color: Color, forward: (i8, i8)) -> Piece {
         let mut moves = Vec::<Move>::with_capacity(4);
-        let enemyC = match color {
-            Color::Black => Color::White,
-            Color::White => Color::Black,
+        let enemy_c = color.opposite();
+
+        // Captures go one step forward and one step to the side.
+        let side: (i8, i8) = (forward.1.abs(), forward.0.abs());
+        let diagonal = (forward.0 + side.0, forward.1 + side.1);
+        let mirror = if forward.0 == 0 {
+            Mirror::Horizontally
+        } else {
+            Mirror::Vertically
         };
-        let mult: (i8, i8) = if color == Color::White {
-            (1, 0)
+
+        // An enemy pawn that just made a double step will be 3 steps from where this pawn started.
+        let passant_line: u8 = if forward.0 + forward.1 > 0 {
+            BOARD_SIZE - 4
         } else {
-            (-1, 1)
+            3
+        };
+        let passant_pos = if forward.0 == 0 {
+            (None, Some(passant_line))
+        } else {
+            (Some(passant_line), None)
         };
 
         // Move forwards:
         moves.push(Move {
             maximum_slide: Some(1),
-            directions: vec![(0, 1 * mult.0)],
+            directions: vec![forward],
             can_capture: false,
             color,
             ..Default::default()
@@ -131,7 +273,7 @@ impl Piece {
         moves.push(Move {
             maximum_slide: Some(2),
             minimum_slide: 2,
-            directions: vec![(0, 1 * mult.0)],
+            directions: vec![forward],
             can_capture: false,
             // Only when it has not moved before.
             requirements: vec![PieceStatus {
@@ -141,16 +283,17 @@ impl Piece {
                 ..Default::default()
             }],
             color,
+            special: Some(Special::DoublePawnStep),
             ..Default::default()
         });
         // Capture
         moves.push(Move {
             maximum_slide: Some(1),
-            directions: vec![(1, 1 * mult.0)],
-            mirror: Some(Mirror::Horizontally),
+            directions: vec![diagonal],
+            mirror: Some(mirror),
             requirements: vec![PieceStatus {
-                relative_pos: Some((1, 1 * mult.0)),
-                color: Some(enemyC),
+                relative_pos: Some(diagonal),
+                color: Some(enemy_c),
                 rank: Some('0'),
                 ..Default::default()
             }],
@@ -160,31 +303,41 @@ impl Piece {
         // En Passant
         moves.push(Move {
             maximum_slide: Some(1),
-            directions: vec![(1, 1 * mult.0)],
-            mirror: Some(Mirror::Horizontally),
+            directions: vec![diagonal],
+            mirror: Some(mirror),
             can_capture: false, // It can not capture in the traditional way.
             requirements: vec![PieceStatus {
                 rank: Some('p'),
-                board_pos: (None, Some((8 * mult.1 + 4 * mult.0) as u8)),
-                relative_pos: Some((1, 0)),
+                board_pos: passant_pos,
+                relative_pos: Some(side),
+                // It has to be its first move, and it has to have been a double step.
                 has_moved: Some((Comparator::Exactly, 1)),
-                color: Some(Color::Black),
+                last_distance: Some(2),
+                color: Some(enemy_c),
                 last_moved: Some(0),
-                ..Default::default()
+                promoted: None,
             }],
             color,
-            effect: vec![Effect::Capture(Position::Relative((1, 0)))],
+            effect: vec![Effect::Capture(Position::Relative(side))],
+            special: Some(Special::EnPassant),
             ..Default::default()
         });
 
         Piece {
             color,
-            can_promote: true,
+            promotion: Some(PromotionRule {
+                squares: None,
+                ranks: PROMOTION_RANKS.to_vec(),
+                color: None,
+            }),
             rank: 'p',
             last_moved: None,
             times_moved: 0,
+            last_distance: 0,
+            promoted: false,
             is_crucial: false,
             moves,
+            forward,
         }
     }
 
@@ -192,10 +345,13 @@ impl Piece {
         Piece {
             color,
             is_crucial: false,
-            can_promote: false,
+            promotion: None,
             rank: 'R',
             last_moved: None,
             times_moved: 0,
+            last_distance: 0,
+            promoted: false,
+            forward: default_forward(color),
             moves: vec![Move {
                 directions: vec![(0, 1), (1, 0)],
                 mirror: Some(Mirror::VerAndHor),
@@ -209,10 +365,13 @@ impl Piece {
         Piece {
             color,
             is_crucial: false,
-            can_promote: false,
+            promotion: None,
             rank: 'B',
             last_moved: None,
             times_moved: 0,
+            last_distance: 0,
+            promoted: false,
+            forward: default_forward(color),
             moves: vec![Move {
                 directions: vec![(1, 1)],
                 mirror: Some(Mirror::VerAndHor),
@@ -226,11 +385,16 @@ impl Piece {
         Piece {
             color,
             is_crucial: false,
-            can_promote: false,
+            promotion: None,
             rank: 'N',
             last_moved: None,
             times_moved: 0,
+            last_distance: 0,
+            promoted: false,
+            forward: default_forward(color),
             moves: vec![Move {
+                // Knights jump, they do not slide.
+                maximum_slide: Some(1),
                 directions: vec![(2, 1), (1, 2)],
                 mirror: Some(Mirror::VerAndHor),
                 color,
@@ -243,10 +407,13 @@ impl Piece {
         Piece {
             color,
             is_crucial: false,
-            can_promote: false,
+            promotion: None,
             rank: 'Q',
             last_moved: None,
             times_moved: 0,
+            last_distance: 0,
+            promoted: false,
+            forward: default_forward(color),
             moves: vec![Move {
                 directions: vec![(0, 1), (1, 1), (1, 0)],
                 mirror: Some(Mirror::VerAndHor),
@@ -257,13 +424,21 @@ impl Piece {
     }
 
     fn new_king(color: Color) -> Piece {
+        // The rooks have to be on the king's starting row, not just the right column.
+        let home_row = match color {
+            Color::White => 0,
+            Color::Black => BOARD_SIZE - 1,
+        };
         Piece {
             color,
             is_crucial: true,
-            can_promote: false,
+            promotion: None,
             rank: 'K',
             last_moved: None,
             times_moved: 0,
+            last_distance: 0,
+            promoted: false,
+            forward: default_forward(color),
             moves: vec![
                 Move {
                     maximum_slide: Some(1),
@@ -277,24 +452,29 @@ impl Piece {
                     maximum_slide: Some(2),
                     minimum_slide: 2,
                     can_capture: false,
-                    color: Color::White,
+                    color,
                     directions: vec![(1, 0)],
                     safe_throughout: true,
                     requirements: vec![
                         PieceStatus {
                             relative_pos: Some((0, 0)),
+                            rank: Some('0'),
                             has_moved: Some((Comparator::Exactly, 0)),
                             ..Default::default()
                         },
+                        // The rook has to be an original one, standing on its home square.
                         PieceStatus {
                             relative_pos: Some((3, 0)),
+                            board_pos: (Some(BOARD_SIZE - 1), Some(home_row)),
                             color: Some(color),
                             rank: Some('R'),
                             has_moved: Some((Comparator::Exactly, 0)),
+                            promoted: Some(false),
                             ..Default::default()
                         },
                     ],
                     command: Some("O-O".to_owned()),
+                    special: Some(Special::Castling),
                     effect: vec![Effect::Move(
                         Position::Relative((3, 0)),
                         Position::Relative((1, 0)),
@@ -306,28 +486,32 @@ impl Piece {
                     maximum_slide: Some(2),
                     minimum_slide: 2,
                     can_capture: false,
-                    color: Color::White,
+                    color,
                     directions: vec![(-1, 0)],
                     safe_throughout: true,
                     requirements: vec![
                         PieceStatus {
                             relative_pos: Some((0, 0)),
+                            rank: Some('0'),
                             has_moved: Some((Comparator::Exactly, 0)),
                             ..Default::default()
                         },
                         PieceStatus {
-                            relative_pos: Some((-3,0)),
+                            relative_pos: Some((-3, 0)),
                             ..Default::default()
                         },
                         PieceStatus {
                             relative_pos: Some((-4, 0)),
+                            board_pos: (Some(0), Some(home_row)),
                             color: Some(color),
                             rank: Some('R'),
                             has_moved: Some((Comparator::Exactly, 0)),
+                            promoted: Some(false),
                             ..Default::default()
                         },
                     ],
                     command: Some("O-O-O".to_owned()),
+                    special: Some(Special::Castling),
                     effect: vec![Effect::Move(
                         Position::Relative((-4, 0)),
                         Position::Relative((-1, 0)),
@@ -1,6 +1,6 @@
 use std::collections::*;
 
-use crate::{Color, Game, GameState};
+use crate::{Color, Game, BOARD_SIZE};
 
 use super::*;
 
@@ -20,7 +20,15 @@ pub enum Mirror {
     VerAndHor,
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Moves that can be turned off with a `RuleSet`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Special {
+    Castling,
+    EnPassant,
+    DoublePawnStep,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
 pub struct PieceStatus {
     pub board_pos: (Option<u8>, Option<u8>),
     pub relative_pos: Option<(i8, i8)>,
@@ -32,25 +40,17 @@ pub struct PieceStatus {
     /// -1 is the turn before, and so on.
     /// 1 is the first turn of the game, 2 is after that, and so on.
     pub last_moved: Option<i32>,
-}
-
-impl Default for PieceStatus {
-    fn default() -> Self {
-        PieceStatus {
-            board_pos: (None, None),
-            relative_pos: None,
-            rank: None,
-            color: None,
-            has_moved: None,
-            last_moved: None,
-        }
-    }
+    /// How many squares the piece went on its last move.
+    pub last_distance: Option<u8>,
+    /// If the piece has to be (or not be) a promoted one.
+    pub promoted: Option<bool>,
 }
 
 #[derive(Clone, Debug)]
 pub struct Move {
     /// Describes the number of times that a move can be repeated in the same direction.
-    /// If None, then there is no limit to the sliding.
+    /// If None, then there is no limit to the sliding, other than the edge of the board.
+    /// Some(0) means the move goes nowhere at all.
     pub maximum_slide: Option<u8>,
     /// If 0, then they will be able to go nowhere.
     pub minimum_slide: u8,
@@ -67,6 +67,11 @@ pub struct Move {
     pub effect: Vec<Effect>,
     /// Apparently, you are unable to castle when in check.
     pub safe_throughout: bool,
+    /// If the move is one of the rules that can be turned off, then this says which one.
+    pub special: Option<Special>,
+    /// If Some, the move has to jump over exactly this many pieces (of any color),
+    /// and can only land on an enemy piece. This is how the cannon from xiangqi captures.
+    pub screens: Option<u8>,
 }
 
 impl Default for Move {
@@ -82,26 +87,54 @@ impl Default for Move {
             command: None,
             effect: Vec::new(),
             safe_throughout: false,
+            special: None,
+            screens: None,
         }
     }
 }
 
 impl Move {
+    /// Every direction of the move, including the mirrored ones.
+    pub fn all_directions(&self) -> Vec<(i8, i8)> {
+        let mut all = Vec::new();
+        for &(c, r) in &self.directions {
+            all.push((c, r));
+            match self.mirror {
+                Some(Mirror::Horizontally) => all.push((-c, r)),
+                Some(Mirror::Vertically) => all.push((c, -r)),
+                Some(Mirror::VerAndHor) => {
+                    all.push((-c, r));
+                    all.push((c, -r));
+                    all.push((-c, -r));
+                }
+                None => {}
+            }
+        }
+        all
+    }
+
     // Make it do a hash map that includes all the extra effects
     pub fn prune(&self, game: &Game, pos: (u8, u8)) -> HashMap<u8, Vec<Effect>> {
         let mut valid = HashMap::<u8, Vec<Effect>>::new();
 
-        if self.safe_throughout && game.is_safe_position(pos.0, pos.1, self.color) {
+        let allowed = match self.special {
+            Some(Special::Castling) => game.rules.castling,
+            Some(Special::EnPassant) => game.rules.en_passant,
+            Some(Special::DoublePawnStep) => game.rules.double_pawn_step,
+            None => true,
+        };
+        if !allowed {
+            return valid;
+        }
+
+        if self.safe_throughout && !game.is_safe_position(pos.0, pos.1, self.color) {
             return valid;
         }
 
         let p_col = pos.0;
         let p_row = pos.1;
 
-        let max_s = match self.maximum_slide {
-            Some(n) => n,
-            _ => 8,
-        };
+        let max_s = self.maximum_slide.unwrap_or(BOARD_SIZE);
 
         for di in &self.directions {
             let d_col = di.0;
@@ -119,6 +152,7 @@ impl Move {
                     &self.color,
                     game,
                     self.safe_throughout,
+                    self.screens,
                 ) {
                     valid.insert(value, self.effect.clone());
                 }
@@ -140,8 +174,9 @@ impl Move {
                         &self.color,
                         game,
                         self.safe_throughout,
+                        self.screens,
                     ) {
-                        valid.insert(value, self.effect.clone());
+                        valid.insert(value, mirror_effects(&self.effect, Mirror::Horizontally));
                     }
                 }
                 if (*m == Mirror::Vertically || *m == Mirror::VerAndHor)
@@ -158,8 +193,9 @@ impl Move {
                         &self.color,
                         game,
                         self.safe_throughout,
+                        self.screens,
                     ) {
-                        valid.insert(value, self.effect.clone());
+                        valid.insert(value, mirror_effects(&self.effect, Mirror::Vertically));
                     }
                 }
                 if *m == Mirror::VerAndHor
@@ -176,8 +212,9 @@ impl Move {
                         &self.color,
                         game,
                         self.safe_throughout,
+                        self.screens,
                     ) {
-                        valid.insert(value, self.effect.clone());
+                        valid.insert(value, mirror_effects(&self.effect, Mirror::VerAndHor));
                     }
                 }
             }
@@ -186,19 +223,39 @@ impl Move {
     }
 }
 
+/// The effects are written for the unmirrored direction, so they need to be flipped along with it.
+fn mirror_effects(effects: &[Effect], mirror: Mirror) -> Vec<Effect> {
+    let flip = |p: Position| match p {
+        Position::Relative((c, r)) => match mirror {
+            Mirror::Horizontally => Position::Relative((-c, r)),
+            Mirror::Vertically => Position::Relative((c, -r)),
+            Mirror::VerAndHor => Position::Relative((-c, -r)),
+        },
+        Position::Global(_) => p,
+    };
+
+    effects
+        .iter()
+        .map(|e| match e {
+            Effect::Capture(p) => Effect::Capture(flip(*p)),
+            Effect::Move(p1, p2) => Effect::Move(flip(*p1), flip(*p2)),
+        })
+        .collect()
+}
+
 fn check_conditions(
     game: &Game,
     pos: (u8, u8),
-    conditions: &Vec<PieceStatus>,
+    conditions: &[PieceStatus],
     mirror: Option<Mirror>,
 ) -> bool {
     for con in conditions {
         // This is quite the wacky math to flip relative positions and board positions.
         // It just makes sense.
-        let mut cdf = 1 as i8;
-        let mut cf = 0 as u8;
-        let mut rdf = 1 as i8;
-        let mut rf = 0 as u8;
+        let mut cdf = 1_i8;
+        let mut cf = 0_u8;
+        let mut rdf = 1_i8;
+        let mut rf = 0_u8;
 
         if let Some(ref m) = mirror {
             if *m == Mirror::Horizontally || *m == Mirror::VerAndHor {
@@ -218,12 +275,12 @@ fn check_conditions(
 
                 // If a board row or column is specified, they must match the relative position.
                 if let Some(c) = con.board_pos.0 {
-                    if col != ((8 * cf) as i8 + c as i8 * cdf) as u8 {
+                    if col != ((BOARD_SIZE * cf) as i8 + c as i8 * cdf) as u8 {
                         return false;
                     }
                 }
                 if let Some(r) = con.board_pos.1 {
-                    if row != ((8 * rf) as i8 + r as i8 * rdf) as u8 {
+                    if row != ((BOARD_SIZE * rf) as i8 + r as i8 * rdf) as u8 {
                         return false;
                     }
                 }
@@ -233,15 +290,17 @@ fn check_conditions(
 
             _ => {
                 // If relative position is not defined, then board position must be defined.
-                let col = ((8 * cf) as i8 + con.board_pos.0.unwrap() as i8 * cdf) as u8;
-                let row = ((8 * rf) as i8 + con.board_pos.1.unwrap() as i8 * rdf) as u8;
+                let col = ((BOARD_SIZE * cf) as i8 + con.board_pos.0.unwrap() as i8 * cdf) as u8;
+                let row = ((BOARD_SIZE * rf) as i8 + con.board_pos.1.unwrap() as i8 * rdf) as u8;
 
                 game.get_piece_at(col, row)
             }
         };
 
         // If everything else is good, then just check if it matches.
-        return check_piece_status(piece, con, game);
+        if !check_piece_status(piece, con, game) {
+            return false;
+        }
     }
     true
 }
@@ -267,10 +326,8 @@ fn check_piece_status(piece: Option<&Piece>, status: &PieceStatus, game: &Game)
 
             // Check if the last move matches
             match status.last_moved {
-                Some(last_move) if last_move > 0 => {
-                    if p.last_moved == None || last_move as u32 != p.last_moved.unwrap() {
-                        return false;
-                    }
+                Some(last_move) if last_move > 0 && p.last_moved != Some(last_move as u32) => {
+                    return false;
                 }
                 Some(last_move) if last_move <= 0 => {
                     if let Some(p_last_move) = p.last_moved {
@@ -292,6 +349,18 @@ fn check_piece_status(piece: Option<&Piece>, status: &PieceStatus, game: &Game)
                 _ => {}
             }
 
+            if let Some(d) = status.last_distance {
+                if p.last_distance != d {
+                    return false;
+                }
+            }
+
+            if let Some(promoted) = status.promoted {
+                if p.promoted != promoted {
+                    return false;
+                }
+            }
+
             // Check if it has moved the right amount of times
             if let Some(cv) = &status.has_moved {
                 use Comparator::*;
@@ -337,6 +406,7 @@ fn check_piece_status(piece: Option<&Piece>, status: &PieceStatus, game: &Game)
     true
 }
 
+#[allow(clippy::too_many_arguments)]
 fn prune_dir(
     p_col: u8,
     p_row: u8,
@@ -348,7 +418,12 @@ fn prune_dir(
     color: &Color,
     game: &Game,
     safe_throughout: bool,
+    screens: Option<u8>,
 ) -> Vec<u8> {
+    if let Some(n) = screens {
+        return prune_screens(p_col, p_row, d_col, d_row, min_s, max_s, color, game, n);
+    }
+
     let mut r = Vec::<u8>::new();
 
     for i in 0..=max_s {
@@ -356,7 +431,7 @@ fn prune_dir(
         let row = p_row as i8 + i as i8 * d_row;
 
         // Do not step outside the edge.
-        if col < 0 || row < 0 || col >= 8 || row >= 8 {
+        if col < 0 || row < 0 || col >= BOARD_SIZE as i8 || row >= BOARD_SIZE as i8 {
             return r;
         }
 
@@ -369,12 +444,12 @@ fn prune_dir(
         match p {
             None => {
                 if i >= min_s {
-                    r.push(col as u8 + row as u8 * 8)
+                    r.push(pack_square(col as u8, row as u8))
                 }
             }
             Some(piece) => {
                 if ((can_capture && piece.color != *color) || i == 0) && i >= min_s {
-                    r.push(col as u8 + row as u8 * 8);
+                    r.push(pack_square(col as u8, row as u8));
                 }
                 // Do not collide with yourself
                 if i > 0 {
@@ -386,3 +461,38 @@ fn prune_dir(
 
     r
 }
+
+/// Like `prune_dir`, but it jumps over `screens` pieces and then has to land on an enemy.
+#[allow(clippy::too_many_arguments)]
+fn prune_screens(
+    p_col: u8,
+    p_row: u8,
+    d_col: i8,
+    d_row: i8,
+    min_s: u8,
+    max_s: u8,
+    color: &Color,
+    game: &Game,
+    screens: u8,
+) -> Vec<u8> {
+    let mut passed = 0;
+    for i in 1..=max_s {
+        let col = p_col as i8 + i as i8 * d_col;
+        let row = p_row as i8 + i as i8 * d_row;
+        if col < 0 || row < 0 || col >= BOARD_SIZE as i8 || row >= BOARD_SIZE as i8 {
+            break;
+        }
+
+        if let Some(piece) = game.get_piece_at(col as u8, row as u8) {
+            if passed < screens {
+                passed += 1;
+                continue;
+            }
+            if piece.color != *color && i >= min_s {
+                return vec![pack_square(col as u8, row as u8)];
+            }
+            break;
+        }
+    }
+    Vec::new()
+}
@@ -0,0 +1,118 @@
+use super::*;
+
+/// Decides how `Game::render` draws the board.
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    /// Put in front of every light square. Usually an ANSI code.
+    pub light_square: String,
+    /// Put in front of every dark square. Usually an ANSI code.
+    pub dark_square: String,
+    /// Write the row numbers to the left and the column letters below.
+    pub show_coordinates: bool,
+    /// The color sitting at the bottom of the board.
+    pub perspective: Color,
+    /// Draw the pieces as ♔ and ♚ instead of (K) and <K>.
+    pub unicode: bool,
+    /// Highlight the piece at this position and every move it can make.
+    pub moves_of: Option<(u8, u8)>,
+}
+
+impl Default for RenderOptions {
+    /// The same look as `print_board` has always had, white pieces as (K) and black as <K>.\
+    /// `print_moves` uses this too, so it no longer draws black pieces as [K].
+    fn default() -> Self {
+        RenderOptions {
+            light_square: "\x1b[7m".to_owned(),
+            dark_square: String::new(),
+            show_coordinates: false,
+            perspective: Color::White,
+            unicode: false,
+            moves_of: None,
+        }
+    }
+}
+
+impl Game {
+    /// Draws the board into a string, one line per row, with 3 characters per square.
+    pub fn render(&self, opts: &RenderOptions) -> String {
+        self.render_with_highlights(&HashSet::new(), opts)
+    }
+
+    /// Like `render`, but every square in `highlights` gets a yellow background.\
+    /// Good for showing the last move, the checked king or a hint.
+    pub fn render_with_highlights(
+        &self,
+        highlights: &HashSet<(u8, u8)>,
+        opts: &RenderOptions,
+    ) -> String {
+        let moves = opts
+            .moves_of
+            .and_then(|(col, row)| self.get_moves(col, row))
+            .unwrap_or_default();
+
+        // Row 0 is the bottom, but the console draws top to bottom.
+        let (rows, cols): (Vec<u8>, Vec<u8>) = match opts.perspective {
+            Color::White => ((0..BOARD_SIZE).rev().collect(), (0..BOARD_SIZE).collect()),
+            Color::Black => ((0..BOARD_SIZE).collect(), (0..BOARD_SIZE).rev().collect()),
+        };
+
+        let mut out = String::new();
+        for &row in &rows {
+            if opts.show_coordinates {
+                out.push_str(&format!("{} ", row + 1));
+            }
+            for &col in &cols {
+                if opts.moves_of == Some((col, row)) {
+                    out.push_str("\x1b[38;5;9m\x1b[48;5;1m");
+                } else if moves.contains_key(&pack_square(col, row)) {
+                    out.push_str("\x1b[38;5;14m\x1b[48;5;14m");
+                } else if highlights.contains(&(col, row)) {
+                    out.push_str("\x1b[38;5;11m\x1b[48;5;11m");
+                }
+                match Game::square_color(col, row) {
+                    Color::White => out.push_str(&opts.light_square),
+                    Color::Black => out.push_str(&opts.dark_square),
+                }
+
+                match self.get_piece_at(col, row) {
+                    Some(p) if opts.unicode => out.push_str(&format!(" {} ", glyph(p))),
+                    Some(p) => match p.color {
+                        Color::White => out.push_str(&format!("({})", p.rank)),
+                        Color::Black => out.push_str(&format!("<{}>", p.rank)),
+                    },
+                    None => out.push_str("   "),
+                }
+                out.push_str("\x1b[0m");
+            }
+            out.push('\n');
+        }
+
+        if opts.show_coordinates {
+            out.push_str("  ");
+            for &col in &cols {
+                out.push_str(&format!(" {} ", (b'a' + col) as char));
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// The unicode chess symbol for the piece, or just its rank if there is none.
+fn glyph(p: &Piece) -> char {
+    match (p.color, p.rank) {
+        (Color::White, 'K') => '♔',
+        (Color::White, 'Q') => '♕',
+        (Color::White, 'R') => '♖',
+        (Color::White, 'B') => '♗',
+        (Color::White, 'N') => '♘',
+        (Color::White, 'p') => '♙',
+        (Color::Black, 'K') => '♚',
+        (Color::Black, 'Q') => '♛',
+        (Color::Black, 'R') => '♜',
+        (Color::Black, 'B') => '♝',
+        (Color::Black, 'N') => '♞',
+        (Color::Black, 'p') => '♟',
+        (_, rank) => rank,
+    }
+}
@@ -0,0 +1,690 @@
+use super::*;
+#[cfg(feature = "random")]
+use rand::{seq::SliceRandom, Rng};
+use std::time::{Duration, Instant};
+
+/// What a checkmate is worth. Anything above this minus the depth is a forced mate.
+pub const MATE_SCORE: i32 = 1_000_000;
+
+/// How good a position is for the turn owner.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Score {
+    /// Pawns times 100, like `Piece::value`.
+    Centipawns(i32),
+    /// Mate in this many moves. Negative if it is the turn owner who gets mated.\
+    /// `Mate(0)` means it already happened, `Game::get_game_state` says who won.
+    Mate(i32),
+}
+
+impl Score {
+    /// Turns a score from the search into a `Score`. `depth` is the depth the search started at.
+    fn from_search(score: i32, depth: u32) -> Score {
+        if score.abs() < MATE_SCORE {
+            return Score::Centipawns(score);
+        }
+        // The search adds the depth that was left when the mate happened.
+        let left = score.abs() - MATE_SCORE;
+        let plies = depth as i32 - left;
+        if score > 0 {
+            Score::Mate((plies + 1) / 2)
+        } else {
+            Score::Mate(-plies / 2)
+        }
+    }
+}
+
+/// Mate scores count the depth that was left when the mate happened, so they only mean something
+/// at the depth they were found at. The table keeps them counted from the position itself instead,
+/// so a position reached at another depth still gets the right distance to the mate.
+fn score_to_tt(score: i32, depth: u32) -> i32 {
+    if score >= MATE_SCORE / 2 {
+        score - depth as i32
+    } else if score <= -MATE_SCORE / 2 {
+        score + depth as i32
+    } else {
+        score
+    }
+}
+
+/// The other way around from `score_to_tt`.
+fn score_from_tt(score: i32, depth: u32) -> i32 {
+    if score >= MATE_SCORE / 2 {
+        score + depth as i32
+    } else if score <= -MATE_SCORE / 2 {
+        score - depth as i32
+    } else {
+        score
+    }
+}
+
+/// Remembers positions the search has already been through, so it does not have to do them again.\
+/// It has a fixed number of slots, and new positions just kick out whatever was in their slot.
+#[derive(Clone, Debug)]
+pub struct TranspositionTable {
+    entries: Vec<Option<TtEntry>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TtEntry {
+    key: u64,
+    depth: u32,
+    score: i32,
+    bound: Bound,
+    best_move: Option<((u8, u8), (u8, u8))>,
+}
+
+/// How much the score of an entry can be trusted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Bound {
+    /// The score is exactly right.
+    Exact,
+    /// The real score is at least this.
+    Lower,
+    /// The real score is at most this.
+    Upper,
+}
+
+impl TranspositionTable {
+    /// A table with room for `size` positions. 0 makes a table that never remembers anything.
+    pub fn new(size: usize) -> TranspositionTable {
+        TranspositionTable {
+            entries: vec![None; size],
+        }
+    }
+
+    /// Forgets everything.
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|e| *e = None);
+    }
+
+    fn probe(&self, key: u64) -> Option<TtEntry> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.entries[(key % self.entries.len() as u64) as usize].filter(|e| e.key == key)
+    }
+
+    fn store(&mut self, entry: TtEntry) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let len = self.entries.len() as u64;
+        self.entries[(entry.key % len) as usize] = Some(entry);
+    }
+}
+
+/// The stuff every position in a search needs.
+struct SearchContext<'a> {
+    eval: &'a dyn Fn(&Game) -> i32,
+    stats: SearchStats,
+    tt: &'a mut TranspositionTable,
+}
+
+/// Scrambles the number, so that close numbers end up nowhere near each other.
+fn splitmix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Everything `Game::analyze` found out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult {
+    /// None if there are no moves to make.
+    pub best_move: Option<ChessMove>,
+    pub score: Score,
+    /// The moves it expects both players to make, starting with `best_move`.
+    pub pv: Vec<ChessMove>,
+    pub stats: SearchStats,
+}
+
+/// Numbers for figuring out if the search got faster or slower.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SearchStats {
+    /// How many positions were looked at. For `perft_stats`, only the ones at the full depth count.
+    pub nodes: u64,
+    /// Positions looked at after the depth ran out, where only captures are tried.
+    pub q_nodes: u64,
+    /// How many times a move was so good that the rest could be skipped.
+    pub cutoffs: u64,
+    pub elapsed: Duration,
+}
+
+impl Game {
+    /// Same as `legal_moves`, but captures come first, sorted by taking the most valuable
+    /// piece with the least valuable piece. Searches prune a lot better this way.
+    pub fn ordered_moves(&self) -> Vec<((u8, u8), (u8, u8))> {
+        let mut moves = self.legal_moves();
+        // The sort is stable, so quiet moves keep their usual order.
+        moves.sort_by_key(|(from, to)| -self.capture_order(*from, *to));
+        moves
+    }
+
+    /// Higher is better. Quiet moves get 0.
+    fn capture_order(&self, from: (u8, u8), to: (u8, u8)) -> i32 {
+        let victim = match self.get_piece_at(to.0, to.1) {
+            Some(p) => p.value(),
+            None => return 0,
+        };
+        let attacker = self.get_piece_at(from.0, from.1).map_or(0, |p| p.value());
+        // Any capture should come before the quiet moves, even pawn for queen.
+        victim * 10 - attacker + 10_000
+    }
+
+    /// Static exchange evaluation. Plays out every capture on `to`, where both sides always
+    /// take with their least valuable piece, and returns what the mover wins (or loses).\
+    /// Either side is allowed to stop capturing when it would only lose more,
+    /// and a king never takes a piece that is still defended.
+    pub fn see(&self, from: (u8, u8), to: (u8, u8)) -> i32 {
+        let mover = match self.get_piece_at(from.0, from.1) {
+            Some(p) => p.clone(),
+            None => return 0,
+        };
+        let target = pack_square(to.0, to.1) as usize;
+
+        let mut g = self.clone();
+        let mut gain = vec![g.board[target].as_ref().map_or(0, |p| p.value())];
+        let mut on_square = mover.value();
+        let mut side = mover.color.opposite();
+        let taken = g.board[pack_square(from.0, from.1) as usize].take();
+        g.put(to.0, to.1, taken);
+
+        loop {
+            // Kings are worth nothing, but they should still capture last.
+            let attacker =
+                g.attackers_of(to.0, to.1, side)
+                    .into_iter()
+                    .min_by_key(|(c, r)| match g.get_piece_at(*c, *r) {
+                        Some(p) if p.is_crucial => i32::MAX,
+                        Some(p) => p.value(),
+                        None => i32::MAX,
+                    });
+            let (col, row) = match attacker {
+                Some(a) => a,
+                None => break,
+            };
+            // Kings capture last, and they can not capture onto a square that is still attacked.
+            if g.get_piece_at(col, row).is_some_and(|p| p.is_crucial) {
+                let mut after = g.clone();
+                let king = after.board[pack_square(col, row) as usize].take();
+                after.put(to.0, to.1, king);
+                if !after.attackers_of(to.0, to.1, side.opposite()).is_empty() {
+                    break;
+                }
+            }
+
+            gain.push(on_square - gain[gain.len() - 1]);
+            on_square = g.get_piece_at(col, row).map_or(0, |p| p.value());
+            let taken = g.board[pack_square(col, row) as usize].take();
+            g.put(to.0, to.1, taken);
+            side = side.opposite();
+        }
+
+        // Go backwards, letting each side decide if it wants to capture at all.
+        while gain.len() > 1 {
+            let last = gain.pop().unwrap();
+            let prev = gain.len() - 1;
+            gain[prev] = -(-gain[prev]).max(last);
+        }
+        gain[0]
+    }
+
+    /// Finds every piece of `color` that the other side can win material by capturing,
+    /// going by `see`. A defended piece can still hang if a cheaper piece attacks it.\
+    /// Kings are never hanging, you have `is_check` for that.
+    pub fn hanging_pieces(&self, color: Color) -> Vec<(u8, u8)> {
+        let enemy = color.opposite();
+
+        let mut all = Vec::new();
+        for i in 0..SQUARES as u8 {
+            let (col, row) = unpack_square(i);
+            match self.get_piece_at(col, row) {
+                Some(p) if p.color == color && !p.is_crucial => {}
+                _ => continue,
+            }
+
+            if self
+                .attackers_of(col, row, enemy)
+                .into_iter()
+                .any(|from| self.see(from, (col, row)) > 0)
+            {
+                all.push((col, row));
+            }
+        }
+        all
+    }
+
+    /// Picks any of the `legal_moves`, all with the same chance. The worst possible opponent.\
+    /// Give it a seeded `rng` if you want the same move every time.
+    #[cfg(feature = "random")]
+    pub fn random_move(&self, rng: &mut impl Rng) -> Option<((u8, u8), (u8, u8))> {
+        self.legal_moves().choose(rng).copied()
+    }
+
+    /// Keeps making `random_move`s (promoting to queens) until the game is over,
+    /// or `max_moves` half moves have been made. Returns the state it ended in, which is `Promote`
+    /// if some piece was not allowed to become a queen.\
+    /// Mostly good for shaking bugs out of the engine.
+    #[cfg(feature = "random")]
+    pub fn play_random_game(&mut self, rng: &mut impl Rng, max_moves: u32) -> GameState {
+        for _ in 0..max_moves {
+            if !matches!(self.game_state, GameState::Running | GameState::Check) {
+                break;
+            }
+            let (from, to) = match self.random_move(rng) {
+                Some(mv) => mv,
+                None => break,
+            };
+            self.make_move(from, to);
+            // A piece that can not become a queen just leaves the game waiting for a promotion.
+            if !self.promote_all('Q') {
+                break;
+            }
+        }
+        self.game_state
+    }
+
+    /// Looks `depth` half moves ahead and picks the move that is best for the turn owner,
+    /// only looking at the material on the board.\
+    /// Returns None if there are no moves to make.
+    pub fn best_move(&self, depth: u32) -> Option<((u8, u8), (u8, u8))> {
+        self.best_move_with(depth, &|g: &Game| g.material_balance())
+    }
+
+    /// Same as `best_move`, but you get to decide how good a position is.\
+    /// `eval` should give a score from White's point of view, the search flips it for Black.
+    pub fn best_move_with(
+        &self,
+        depth: u32,
+        eval: &dyn Fn(&Game) -> i32,
+    ) -> Option<((u8, u8), (u8, u8))> {
+        self.analyze_with(depth, eval)
+            .best_move
+            .map(|m| (m.from, m.to))
+    }
+
+    /// Same as `best_move`, but you also get the score, the moves it expects to be played,
+    /// and how many positions it looked at.
+    pub fn analyze(&self, depth: u32) -> SearchResult {
+        self.analyze_with(depth, &|g: &Game| g.material_balance())
+    }
+
+    /// Same as `analyze`, but with your own `eval`, like `best_move_with`.
+    pub fn analyze_with(&self, depth: u32, eval: &dyn Fn(&Game) -> i32) -> SearchResult {
+        self.search(depth, eval, &mut TranspositionTable::new(0))
+    }
+
+    /// Same as `best_move`, but it remembers positions it has already searched in `tt`.\
+    /// Keep the table around between calls, and it gets even better.
+    pub fn best_move_tt(
+        &self,
+        depth: u32,
+        tt: &mut TranspositionTable,
+    ) -> Option<((u8, u8), (u8, u8))> {
+        self.analyze_tt(depth, tt).best_move.map(|m| (m.from, m.to))
+    }
+
+    /// Same as `analyze`, but with a transposition table, like `best_move_tt`.
+    pub fn analyze_tt(&self, depth: u32, tt: &mut TranspositionTable) -> SearchResult {
+        self.search(depth, &|g: &Game| g.material_balance(), tt)
+    }
+
+    fn search(
+        &self,
+        depth: u32,
+        eval: &dyn Fn(&Game) -> i32,
+        tt: &mut TranspositionTable,
+    ) -> SearchResult {
+        let start = Instant::now();
+        // Every node is a copy, and none of them need to know how the game got here.
+        let root = self.clone_without_history();
+        let mut ctx = SearchContext {
+            eval,
+            stats: SearchStats {
+                nodes: 1,
+                ..Default::default()
+            },
+            tt,
+        };
+        let mut result = SearchResult {
+            best_move: None,
+            score: Score::Centipawns(0),
+            pv: Vec::new(),
+            stats: SearchStats::default(),
+        };
+        let mut alpha = -i32::MAX;
+
+        for (from, to) in root.legal_moves() {
+            if let Some(g) = root.after_move(from, to) {
+                let mut line = Vec::new();
+                let score = -g.negamax(
+                    depth.saturating_sub(1),
+                    -i32::MAX,
+                    -alpha,
+                    &mut ctx,
+                    &mut line,
+                );
+                if result.best_move.is_none() || score > alpha {
+                    alpha = score;
+                    let mv = root.queen_move(from, to);
+                    result.best_move = Some(mv);
+                    result.pv = vec![mv];
+                    result.pv.append(&mut line);
+                }
+            }
+        }
+
+        if result.best_move.is_some() {
+            result.score = Score::from_search(alpha, depth);
+        } else if let Some(score) = root.terminal_score(depth) {
+            // No moves because the game is already over, so say how it ended.
+            result.score = Score::from_search(score, depth);
+        }
+        result.stats = ctx.stats;
+        result.stats.elapsed = start.elapsed();
+        result
+    }
+
+    /// What the game is worth if it is already over, seen from the turn owner. `None` if it isn't.
+    fn terminal_score(&self, depth: u32) -> Option<i32> {
+        match self.game_state {
+            // Mates closer to the root are worse for the one getting mated.
+            GameState::CheckMate | GameState::CrucialCaptured => Some(-MATE_SCORE - depth as i32),
+            GameState::Stalemate => Some(0),
+            GameState::StalemateWin => Some(MATE_SCORE + depth as i32),
+            _ => None,
+        }
+    }
+
+    /// The score is always seen from the turn owner's point of view.\
+    /// `pv` gets the best line found from here, if any move beat `alpha`.
+    fn negamax(
+        &self,
+        depth: u32,
+        mut alpha: i32,
+        beta: i32,
+        ctx: &mut SearchContext,
+        pv: &mut Vec<ChessMove>,
+    ) -> i32 {
+        ctx.stats.nodes += 1;
+        if let Some(score) = self.terminal_score(depth) {
+            return score;
+        }
+
+        if depth == 0 {
+            return self.quiesce(alpha, beta, ctx);
+        }
+
+        let key = self.zobrist();
+        let mut tt_move = None;
+        if let Some(entry) = ctx.tt.probe(key) {
+            if entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => {
+                        *pv = self.tt_line(ctx.tt, depth);
+                        return score_from_tt(entry.score, depth);
+                    }
+                    Bound::Lower if score_from_tt(entry.score, depth) >= beta => return beta,
+                    Bound::Upper if score_from_tt(entry.score, depth) <= alpha => return alpha,
+                    _ => {}
+                }
+            }
+            tt_move = entry.best_move;
+        }
+
+        let mut moves = self.ordered_moves();
+        // Whatever was best last time is probably still good.
+        if let Some(m) = tt_move {
+            if let Some(i) = moves.iter().position(|&x| x == m) {
+                let m = moves.remove(i);
+                moves.insert(0, m);
+            }
+        }
+
+        let alpha_start = alpha;
+        let mut best_move = None;
+        for (from, to) in moves {
+            if let Some(g) = self.after_move(from, to) {
+                let mut line = Vec::new();
+                let score = -g.negamax(depth - 1, -beta, -alpha, ctx, &mut line);
+                if score >= beta {
+                    ctx.stats.cutoffs += 1;
+                    ctx.tt.store(TtEntry {
+                        key,
+                        depth,
+                        score: score_to_tt(beta, depth),
+                        bound: Bound::Lower,
+                        best_move: Some((from, to)),
+                    });
+                    return beta;
+                }
+                if score > alpha {
+                    alpha = score;
+                    best_move = Some((from, to));
+                    pv.clear();
+                    pv.push(self.queen_move(from, to));
+                    pv.append(&mut line);
+                }
+            }
+        }
+
+        ctx.tt.store(TtEntry {
+            key,
+            depth,
+            score: score_to_tt(alpha, depth),
+            bound: if alpha > alpha_start {
+                Bound::Exact
+            } else {
+                Bound::Upper
+            },
+            best_move,
+        });
+        alpha
+    }
+
+    /// Keeps going after the depth runs out, but only with captures, so the search does not stop
+    /// right in the middle of a trade and think it is a piece up.\
+    /// Either side can stop capturing whenever it wants, so the eval is the worst it can get.
+    fn quiesce(&self, mut alpha: i32, beta: i32, ctx: &mut SearchContext) -> i32 {
+        let stand_pat = match self.turn_owner {
+            Color::White => (ctx.eval)(self),
+            Color::Black => -(ctx.eval)(self),
+        };
+        if stand_pat >= beta {
+            return beta;
+        }
+        alpha = alpha.max(stand_pat);
+
+        // Captures are at the front of the ordered moves, so stop at the first quiet one.
+        for (from, to) in self.ordered_moves() {
+            if self.capture_order(from, to) == 0 {
+                break;
+            }
+            if let Some(g) = self.after_move(from, to) {
+                ctx.stats.q_nodes += 1;
+                let score = match g.terminal_score(0) {
+                    Some(score) => -score,
+                    None => -g.quiesce(-beta, -alpha, ctx),
+                };
+                if score >= beta {
+                    ctx.stats.cutoffs += 1;
+                    return beta;
+                }
+                alpha = alpha.max(score);
+            }
+        }
+        alpha
+    }
+
+    /// Follows the best moves of exact entries in the table, at most `depth` of them.\
+    /// That is the line a search hit in the table would have found, so the PV does not stop there.
+    fn tt_line(&self, tt: &TranspositionTable, depth: u32) -> Vec<ChessMove> {
+        let mut line = Vec::new();
+        let mut g = self.clone();
+        for _ in 0..depth {
+            let (from, to) = match tt.probe(g.zobrist()) {
+                Some(TtEntry {
+                    bound: Bound::Exact,
+                    best_move: Some(m),
+                    ..
+                }) => m,
+                _ => break,
+            };
+            let mv = g.queen_move(from, to);
+            g = match g.after_move(from, to) {
+                Some(next) => next,
+                None => break,
+            };
+            line.push(mv);
+        }
+        line
+    }
+
+    /// A number for the position, which is (almost certainly) different for different positions.\
+    /// The pieces, who's turn it is, the castling and en passant rights and the pieces in hand are
+    /// included, the same things that make two positions the same for `is_threefold_now`.
+    pub fn zobrist(&self) -> u64 {
+        let mut hash = 0;
+        for (i, square) in self.board.iter().enumerate() {
+            if let Some(p) = square {
+                let feature = (i as u64 * 2 + p.color.index() as u64) << 8 | p.rank as u64;
+                hash ^= splitmix(feature + 1);
+            }
+        }
+        if self.turn_owner == Color::Black {
+            hash ^= splitmix(u64::MAX);
+        }
+        // The rights get numbers far away from anything a piece could use.
+        let castling = self.castling_rights();
+        if castling != 0 {
+            hash ^= splitmix(castling).rotate_left(1);
+        }
+        if let Some(sq) = self.en_passant_square() {
+            hash ^= splitmix(u64::MAX - 1 - sq as u64);
+        }
+        for (color, hand) in self.sorted_hands().iter().enumerate() {
+            for (k, &rank) in hand.iter().enumerate() {
+                // The second knight in hand gets another number than the first one.
+                let nth = hand[..k].iter().filter(|&&r| r == rank).count() as u64;
+                hash ^= splitmix(1 << 40 | nth << 16 | (color as u64) << 8 | rank as u64);
+            }
+        }
+        hash
+    }
+
+    /// The move as a `ChessMove`, promoting to a queen like `after_move` does.
+    fn queen_move(&self, from: (u8, u8), to: (u8, u8)) -> ChessMove {
+        let promotes = self
+            .get_piece_at(from.0, from.1)
+            .is_some_and(|p| p.promotes_at(to.0, to.1));
+        ChessMove {
+            from,
+            to,
+            promotion: if promotes { Some('Q') } else { None },
+        }
+    }
+
+    /// Counts every sequence of `depth` legal moves from here. Good for finding bugs in the move generation.\
+    /// Promotions count once for every rank the pawn can become, like everyone else counts them.
+    pub fn perft(&self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        let moves = self.legal_chess_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+
+        let mut total = 0;
+        for mv in moves {
+            let mut g = self.clone_without_history();
+            if g.make(mv).is_ok() {
+                total += g.perft(depth - 1);
+            }
+        }
+        total
+    }
+
+    /// Same as `perft`, but split up by the first move, in `legal_moves_sorted` order.
+    /// The promotion ranks of a move are added together. Empty for depth 0.\
+    /// Compare it with another engine's numbers to find out which move is the broken one.
+    #[allow(clippy::type_complexity)]
+    pub fn perft_divide(&self, depth: u32) -> Vec<(((u8, u8), (u8, u8)), u64)> {
+        if depth == 0 {
+            return Vec::new();
+        }
+
+        self.legal_moves_sorted()
+            .into_iter()
+            .map(|(from, to)| {
+                let count = self
+                    .legal_moves_from(from.0, from.1)
+                    .into_iter()
+                    .filter(|mv| mv.to == to)
+                    .map(|mv| {
+                        let mut g = self.clone_without_history();
+                        match g.make(mv) {
+                            Ok(()) => g.perft(depth - 1),
+                            Err(_) => 0,
+                        }
+                    })
+                    .sum();
+                ((from, to), count)
+            })
+            .collect()
+    }
+
+    /// How many moves there are on average after `depth - 1` moves, using the perft counts.\
+    /// Depth 1 is just the number of moves from here. Gives 0.0 for depth 0, or if the game ends before `depth - 1`.
+    pub fn branching_factor(&self, depth: u32) -> f64 {
+        if depth == 0 {
+            return 0.0;
+        }
+        let (counts, _) = self.perft_stats(depth);
+        let before = if depth == 1 {
+            1
+        } else {
+            counts[depth as usize - 2]
+        };
+        if before == 0 {
+            return 0.0;
+        }
+        counts[depth as usize - 1] as f64 / before as f64
+    }
+
+    /// Same as `perft`, but you also get how many positions there are after each number of moves,
+    /// starting with 1. `stats.nodes` is the same number `perft` would give you.
+    pub fn perft_stats(&self, depth: u32) -> (Vec<u64>, SearchStats) {
+        let start = Instant::now();
+        let mut counts = vec![0; depth as usize];
+        self.perft_count(&mut counts, 0);
+
+        let stats = SearchStats {
+            nodes: counts.last().copied().unwrap_or(1),
+            elapsed: start.elapsed(),
+            ..Default::default()
+        };
+        (counts, stats)
+    }
+
+    fn perft_count(&self, counts: &mut [u64], ply: usize) {
+        if ply >= counts.len() {
+            return;
+        }
+
+        let moves = self.legal_chess_moves();
+        counts[ply] += moves.len() as u64;
+        if ply + 1 == counts.len() {
+            return;
+        }
+
+        for mv in moves {
+            let mut g = self.clone_without_history();
+            if g.make(mv).is_ok() {
+                g.perft_count(counts, ply + 1);
+            }
+        }
+    }
+}